@@ -0,0 +1,455 @@
+//! A global cache of fetched repositories, shared by all workspaces.
+//! Every commit is stored once in `~/.grill/cache/<commit>`, and
+//! workspaces get their packages from there.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    config,
+    index::unix_now,
+    paths::{self, DirLock, TempDir},
+};
+
+/// How packages in the cache are put into a workspace.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Copy the whole repository.
+    #[default]
+    Copy,
+    /// Hard link the files, except the project files that grill changes.
+    /// Other changes to the files also change the cache, so modified
+    /// entries are fetched again.
+    Hardlink,
+    /// Check out the files, with the git objects referenced from the cache.
+    /// Before an entry is removed, its objects are copied into the
+    /// workspaces that reference them.
+    Reference,
+}
+
+/// Metadata stored next to each cached repository.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct EntryInfo {
+    url: Url,
+    /// Unix time of when the entry was last put into a workspace.
+    last_used: u64,
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub rev: String,
+    pub path: PathBuf,
+    pub url: Option<Url>,
+    pub last_used: u64,
+}
+
+impl Entry {
+    /// The size of the repository on disk, in bytes.
+    pub fn size(&self) -> u64 {
+        dir_size(&self.path).unwrap_or_default()
+    }
+}
+
+/// The repositories cached in a directory.
+#[derive(Debug)]
+pub struct Cache {
+    root: PathBuf,
+    /// Where repositories are cloned to before they are moved into the cache.
+    /// Has to be on the same file system as `root`.
+    tmp: PathBuf,
+    offline: bool,
+}
+
+impl Cache {
+    pub fn new(root: PathBuf, tmp: PathBuf, offline: bool) -> Self {
+        Self { root, tmp, offline }
+    }
+
+    /// The cache in `~/.grill/cache`.
+    pub fn global() -> Self {
+        Self::new(paths::cache(), paths::tmp(), config::get().offline())
+    }
+
+    /// Locks the cache while entries are added or removed.
+    pub fn lock(&self) -> Result<DirLock> {
+        paths::lock(&self.root)
+    }
+
+    /// Path of a cached commit.
+    pub fn path(&self, rev: &str) -> PathBuf {
+        self.root.join(rev)
+    }
+
+    fn info_path(&self, rev: &str) -> PathBuf {
+        self.root.join(format!("{}.toml", rev))
+    }
+
+    /// The checkouts that use the objects of a cached commit.
+    fn references_path(&self, rev: &str) -> PathBuf {
+        self.root.join(format!("{}.references", rev))
+    }
+
+    /// Returns true if `rev` is a full commit id that is in the cache.
+    pub fn contains(&self, rev: &str) -> bool {
+        git2::Oid::from_str(rev).is_ok() && rev.len() == 40 && self.path(rev).exists()
+    }
+
+    /// Returns the cached repository checked out at `rev`, and the id of
+    /// the commit. Without `rev` the default branch is used. The repository
+    /// is only fetched if the commit isn't cached already.
+    pub fn fetch<C>(
+        &self,
+        url: &Url,
+        rev: Option<&str>,
+        mut progress_callback: C,
+    ) -> Result<(PathBuf, String)>
+    where
+        C: FnMut(git2::Progress<'_>),
+    {
+        if let Some(rev) = rev.filter(|rev| self.contains(rev)) {
+            if !is_modified(&self.path(rev)) {
+                log::debug!("Using cached {} for {}", rev, url);
+                self.touch(rev, url)?;
+                return Ok((self.path(rev), rev.to_owned()));
+            }
+
+            // Changed through a hard link in a workspace
+            log::debug!("Cached {} for {} was modified, fetching it again", rev, url);
+            let _lock = self.lock()?;
+            self.remove_entry(rev)?;
+        }
+
+        if self.offline {
+            bail!("Cannot fetch {} in offline mode", url);
+        }
+
+        // Packages are fetched in parallel, so every clone needs its own directory
+        let tmp = TempDir::new_in(&self.tmp)?;
+
+        let commit;
+        {
+            let mut callbacks = git2::RemoteCallbacks::new();
+            callbacks.transfer_progress(|p| {
+                progress_callback(p);
+                true
+            });
+
+            let mut fo = git2::FetchOptions::new();
+            fo.remote_callbacks(callbacks);
+            let repo = git2::build::RepoBuilder::new()
+                .fetch_options(fo)
+                .clone(url.as_str(), tmp.path())?;
+
+            let (object, reference) = match rev {
+                Some(rev) => repo.revparse_ext(rev)?,
+                None => (repo.head()?.peel(git2::ObjectType::Commit)?, None),
+            };
+            repo.checkout_tree(&object, None)?;
+            match reference {
+                Some(gref) => repo.set_head(gref.name().with_context(|| "Invalid gref name")?),
+                None => repo.set_head_detached(object.id()),
+            }?;
+
+            commit = object.peel_to_commit()?.id().to_string();
+
+            // Dropping the repository gives us access to the directory
+        }
+
+        let path = self.path(&commit);
+        let _lock = self.lock()?;
+        if !path.exists() {
+            if let Err(err) = fs::rename(tmp.path(), &path) {
+                // Another fetch of the same commit may have finished first
+                if !path.exists() {
+                    return Err(err).context("Failed to move tmp folder");
+                }
+            }
+        }
+        self.touch(&commit, url)?;
+
+        Ok((path, commit))
+    }
+
+    fn touch(&self, rev: &str, url: &Url) -> Result<()> {
+        let info = EntryInfo {
+            url: url.clone(),
+            last_used: unix_now(),
+        };
+        fs::write(self.info_path(rev), toml::to_string(&info)?)
+            .context("Failed to write cache entry")
+    }
+
+    /// Puts the cached commit `rev` into `dest`, which must not exist.
+    pub fn populate(&self, rev: &str, dest: &Path, mode: CacheMode) -> Result<()> {
+        log::debug!("Populating {} from the cache ({:?})", dest.display(), mode);
+        let cached = self.path(rev);
+        match mode {
+            CacheMode::Copy => copy_dir(&cached, dest, false)?,
+            CacheMode::Hardlink => copy_dir(&cached, dest, true)?,
+            CacheMode::Reference => {
+                // The entry can't be removed until the reference is recorded
+                let _lock = self.lock()?;
+                if !cached.exists() {
+                    bail!("{} was removed from the cache, try again", rev);
+                }
+
+                let head = git2::Repository::open(&cached)?
+                    .head()?
+                    .peel_to_commit()?
+                    .id();
+
+                git2::Repository::init(dest)?;
+                fs::write(
+                    alternates_path(dest),
+                    format!("{}\n", objects_path(&cached).display()),
+                )?;
+
+                // Open the repository again to use the alternate objects
+                let repo = git2::Repository::open(dest)?;
+                repo.set_head_detached(head)?;
+                repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+
+                let mut references = self.references(rev);
+                references.push(dest.canonicalize()?);
+                self.write_references(rev, &references)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The checkouts that still use the objects of a cached commit.
+    fn references(&self, rev: &str) -> Vec<PathBuf> {
+        let objects = objects_path(&self.path(rev));
+        fs::read_to_string(self.references_path(rev))
+            .unwrap_or_default()
+            .lines()
+            .map(PathBuf::from)
+            .filter(|checkout| {
+                fs::read_to_string(alternates_path(checkout)).is_ok_and(|alternates| {
+                    alternates.lines().any(|line| Path::new(line) == objects)
+                })
+            })
+            .collect()
+    }
+
+    fn write_references(&self, rev: &str, references: &[PathBuf]) -> Result<()> {
+        let references: String = references
+            .iter()
+            .map(|checkout| format!("{}\n", checkout.display()))
+            .collect();
+        fs::write(self.references_path(rev), references).context("Failed to write cache references")
+    }
+
+    /// All cached repositories.
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+
+            let rev = path
+                .file_name()
+                .context("Invalid cache entry")?
+                .to_string_lossy()
+                .to_string();
+            let info: Option<EntryInfo> = fs::read_to_string(self.info_path(&rev))
+                .ok()
+                .and_then(|info| toml::from_str(&info).ok());
+
+            entries.push(Entry {
+                url: info.as_ref().map(|info| info.url.clone()),
+                last_used: info.map(|info| info.last_used).unwrap_or_default(),
+                rev,
+                path,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Removes a cached repository. Workspaces that reference its objects
+    /// get a copy of them first. The caller has to hold [`Cache::lock`].
+    pub fn remove(&self, entry: &Entry) -> Result<()> {
+        self.remove_entry(&entry.rev)
+    }
+
+    fn remove_entry(&self, rev: &str) -> Result<()> {
+        let objects = objects_path(&self.path(rev));
+        for checkout in self.references(rev) {
+            log::debug!("Copying the objects of {} into {}", rev, checkout.display());
+            copy_dir(&objects, &objects_path(&checkout), false).with_context(|| {
+                format!("Failed to copy git objects into {}", checkout.display())
+            })?;
+            fs::remove_file(alternates_path(&checkout))?;
+        }
+
+        rm_rf::ensure_removed(self.path(rev))?;
+        rm_rf::ensure_removed(self.info_path(rev))?;
+        rm_rf::ensure_removed(self.references_path(rev))?;
+        Ok(())
+    }
+}
+
+fn objects_path(repo: &Path) -> PathBuf {
+    repo.join(".git").join("objects")
+}
+
+fn alternates_path(repo: &Path) -> PathBuf {
+    objects_path(repo).join("info").join("alternates")
+}
+
+/// Returns true if files tracked in the cached repository have changed.
+fn is_modified(path: &Path) -> bool {
+    let Ok(repo) = git2::Repository::open(path) else {
+        return true;
+    };
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(false);
+    repo.statuses(Some(&mut options))
+        .map_or(true, |statuses| !statuses.is_empty())
+}
+
+/// Copies a directory recursively, keeping files that already exist.
+/// If `hardlink` is set, files are hard linked instead, except for
+/// `BeefProj.toml` files which grill rewrites.
+fn copy_dir(from: &Path, to: &Path, hardlink: bool) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let to = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to, hardlink)?;
+        } else if to.exists() {
+            continue;
+        } else if !hardlink
+            || entry.file_name() == "BeefProj.toml"
+            || fs::hard_link(entry.path(), &to).is_err()
+        {
+            fs::copy(entry.path(), &to)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use url::Url;
+
+    use super::{Cache, CacheMode};
+
+    /// A cache in a temporary directory, and a repository with one commit.
+    fn setup() -> (tempfile::TempDir, Cache, Url, String) {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["cache", "tmp", "upstream"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+        }
+        let cache = Cache::new(dir.path().join("cache"), dir.path().join("tmp"), false);
+
+        let upstream = dir.path().join("upstream");
+        let repo = git2::Repository::init(&upstream).unwrap();
+        fs::write(upstream.join("Package.toml"), "").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("Package.toml")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("grill", "grill@localhost").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "Initial", &tree, &[])
+            .unwrap()
+            .to_string();
+        let url = Url::from_directory_path(&upstream).unwrap();
+
+        (dir, cache, url, commit)
+    }
+
+    fn head(path: &Path) -> String {
+        let repo = git2::Repository::open(path).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap().id();
+        head.to_string()
+    }
+
+    #[test]
+    fn fetch() {
+        let (dir, cache, url, commit) = setup();
+
+        let (cached, rev) = cache.fetch(&url, None, |_| {}).unwrap();
+        assert_eq!(rev, commit);
+        assert!(cache.contains(&commit));
+        assert_eq!(cache.fetch(&url, Some(&commit), |_| {}).unwrap().0, cached);
+
+        // Cached commits don't need the network
+        let offline = Cache::new(dir.path().join("cache"), dir.path().join("tmp"), true);
+        assert!(offline.fetch(&url, Some(&commit), |_| {}).is_ok());
+        assert!(offline.fetch(&url, None, |_| {}).is_err());
+
+        for mode in [CacheMode::Copy, CacheMode::Hardlink, CacheMode::Reference] {
+            let dest = dir.path().join(format!("{:?}", mode));
+            cache.populate(&commit, &dest, mode).unwrap();
+            assert_eq!(head(&dest), commit);
+        }
+
+        // Changing a hard linked file changes the cache, which is then fetched again
+        fs::write(dir.path().join("Hardlink").join("Package.toml"), "changed").unwrap();
+        assert!(super::is_modified(&cached));
+        let (cached, _) = cache.fetch(&url, Some(&commit), |_| {}).unwrap();
+        assert!(!super::is_modified(&cached));
+        assert_eq!(fs::read_to_string(cached.join("Package.toml")).unwrap(), "");
+
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].rev, commit);
+        assert_eq!(entries[0].url.as_ref(), Some(&url));
+
+        let lock = cache.lock().unwrap();
+        cache.remove(&entries[0]).unwrap();
+        drop(lock);
+        assert!(cache.entries().unwrap().is_empty());
+        assert!(!cache.contains(&commit));
+    }
+
+    #[test]
+    fn remove_referenced() {
+        let (dir, cache, url, commit) = setup();
+        cache.fetch(&url, None, |_| {}).unwrap();
+
+        let dest = dir.path().join("ws");
+        cache
+            .populate(&commit, &dest, CacheMode::Reference)
+            .unwrap();
+        assert_eq!(cache.references(&commit), [dest.canonicalize().unwrap()]);
+
+        // The workspace keeps working after the entry is gone
+        let entries = cache.entries().unwrap();
+        cache.remove(&entries[0]).unwrap();
+        assert!(!super::alternates_path(&dest).exists());
+        assert_eq!(head(&dest), commit);
+        let repo = git2::Repository::open(&dest).unwrap();
+        assert!(repo.head().unwrap().peel_to_tree().is_ok());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod add;
+pub mod cache;
+pub mod info;
 pub mod init;
 pub mod install;
 pub mod list;
@@ -8,4 +10,13 @@ pub mod new;
 pub mod publish;
 pub mod purge;
 pub mod rebuild;
+pub mod search;
 pub mod update;
+pub mod yank;
+
+use crate::prelude::*;
+
+/// If `--locked` or `--frozen` was passed, so the lock file must not change.
+pub fn locked(args: &ArgMatches) -> bool {
+    args.is_present("locked") || args.is_present("frozen")
+}
@@ -16,39 +16,59 @@ pub fn cli() -> App {
             Arg::new("packages")
                 .value_name("PACKAGE")
                 .required(true)
-                .multiple_values(true),
+                .multiple_values(true)
+                .help("Packages to add, use <PACKAGE>@next for the latest pre-release"),
+        )
+        .arg(
+            Arg::new("allow-prerelease")
+                .long("allow-prerelease")
+                .help("Use the latest version even if it is a pre-release"),
         )
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
-    let packages: Vec<&String> = args
-        .get_many("packages")
+    let allow_prerelease = args.is_present("allow-prerelease");
+    let packages = args
+        .get_many::<String>("packages")
         .expect("Packages need to be specified")
-        .collect();
+        .map(|package| match package.split_once('@') {
+            Some((name, "next")) => Ok((name.to_owned(), true)),
+            Some((_, tag)) => bail!(
+                "Unknown tag '{}' in '{}', only @next is supported",
+                tag,
+                package
+            ),
+            None => Ok((package.clone(), allow_prerelease)),
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    let index = index::parse(true, false)?;
+    let index = index::load(packages.iter().map(|(name, _)| name), true, false)?;
     let mut manifest = Manifest::from_pkg(".")?;
 
     let mut already_added = false;
-    for package in packages {
-        if manifest.dependencies.contains_key(package) {
+    for (package, allow_prerelease) in packages {
+        if manifest.dependencies.contains_key(&package) {
             already_added = true;
             log::info!("{} is already specified in the manifest.", package);
             continue;
         }
 
-        let entry = index.get(package).with_context(|| {
+        let entry = index.get(&package).with_context(|| {
             format!(
-                "'{}' could not be found. If you know this package exists, try updating the index with $ grill update --index", 
+                "'{}' could not be found. Look for packages with $ grill search, or if you know this package exists, try updating the index with $ grill update --index",
                 package
             )
         })?;
 
-        let latest = entry
-            .versions
-            .keys()
-            .max()
-            .expect("No version found for this package");
+        let (latest, _) = match entry.latest(allow_prerelease) {
+            Some(latest) => latest,
+            None if entry.latest(true).is_some() => bail!(
+                "'{}' only has pre-release versions. Use --allow-prerelease or {}@next to add it anyway",
+                package,
+                package
+            ),
+            None => bail!("No available versions of '{}'", package),
+        };
 
         let mut req = VersionReq::default();
         req.comparators.push(Comparator {
@@ -61,11 +81,13 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
 
         manifest
             .dependencies
-            .insert(package.clone(), Dependency::Simple(req));
+            .insert(package, Dependency::Simple(req));
     }
 
     fs::write(crate::paths::MANIFEST_FILENAME, toml::to_string(&manifest)?)?;
-    lock::generate(Path::new("."), true, true)?;
+    lock::generate(Path::new("."), true, true, super::locked(args), |missing| {
+        crate::ops::fetch_source(Path::new("."), missing)
+    })?;
 
     if already_added {
         println!();
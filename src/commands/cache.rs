@@ -0,0 +1,142 @@
+use console::style;
+
+use crate::{
+    cache::{Cache, Entry},
+    index::unix_now,
+    prelude::*,
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+pub fn cli() -> App {
+    App::new("cache")
+        .about("Manage the global package cache")
+        .subcommand_required(true)
+        .subcommand(App::new("list").about("List the cached packages"))
+        .subcommand(
+            App::new("clean").about("Delete all cached packages").arg(
+                Arg::new("yes")
+                    .long("yes")
+                    .short('y')
+                    .help("Don't ask for confirmation"),
+            ),
+        )
+        .subcommand(
+            App::new("prune")
+                .about("Delete cached packages that haven't been used recently")
+                .arg(
+                    Arg::new("days")
+                        .long("days")
+                        .value_name("DAYS")
+                        .default_value("30")
+                        .help("Delete packages that haven't been used in this many days"),
+                ),
+        )
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let cache = Cache::global();
+    // Listing doesn't change the cache, so it doesn't wait for other processes
+    let _lock = (args.subcommand_name() != Some("list"))
+        .then(|| cache.lock())
+        .transpose()?;
+    let mut entries = cache.entries()?;
+    entries.sort_by(|a, b| (&a.url, &a.rev).cmp(&(&b.url, &b.rev)));
+
+    match args.subcommand() {
+        Some(("list", _)) => {
+            if entries.is_empty() {
+                println!("No packages in cache");
+                return Ok(());
+            }
+
+            let mut total = 0;
+            for entry in entries.iter() {
+                let size = entry.size();
+                total += size;
+                println!(
+                    "{} {} {}",
+                    style(
+                        entry
+                            .url
+                            .as_ref()
+                            .map_or_else(|| String::from("unknown"), ToString::to_string)
+                    )
+                    .bright()
+                    .cyan(),
+                    style(&entry.rev[..entry.rev.len().min(7)]).dim(),
+                    format_size(size)
+                );
+            }
+            println!("\n{} packages, {}", entries.len(), format_size(total));
+        }
+        Some(("clean", args)) => {
+            if entries.is_empty() {
+                println!("No packages in cache");
+                return Ok(());
+            }
+
+            if !args.is_present("yes")
+                && !dialoguer::Confirm::new()
+                    .with_prompt(format!(
+                        "Are you sure you want to delete {} cached packages?",
+                        entries.len()
+                    ))
+                    .interact()?
+            {
+                return Ok(());
+            }
+
+            remove(&cache, &entries)?;
+        }
+        Some(("prune", args)) => {
+            let days: u64 = args
+                .value_of("days")
+                .unwrap()
+                .parse()
+                .context("Invalid number of days")?;
+            let now = unix_now();
+            let unused: Vec<Entry> = entries
+                .into_iter()
+                .filter(|entry| now.saturating_sub(entry.last_used) >= days * DAY)
+                .collect();
+
+            remove(&cache, &unused)?;
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn remove(cache: &Cache, entries: &[Entry]) -> Result<()> {
+    let mut size = 0;
+    for entry in entries {
+        size += entry.size();
+        cache.remove(entry)?;
+    }
+
+    println!(
+        "{:>12} {} packages, {}",
+        style("Deleted").bright().red(),
+        entries.len(),
+        format_size(size)
+    );
+    Ok(())
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
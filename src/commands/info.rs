@@ -0,0 +1,123 @@
+use console::style;
+use itertools::Itertools;
+use semver::VersionReq;
+use serde_json::json;
+
+use crate::{index, prelude::*};
+
+pub fn cli() -> App {
+    App::new("info")
+        .about("Show the versions and dependencies of a package")
+        .arg(
+            Arg::new("package")
+                .value_name("PACKAGE[@REQ]")
+                .required(true)
+                .help("The package, optionally with a version requirement"),
+        )
+        .arg(Arg::new("json").long("json").help("Output as JSON"))
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let package = args.value_of("package").unwrap();
+    let (name, req) = match package.split_once('@') {
+        Some((name, req)) => (
+            name,
+            Some(VersionReq::parse(req).context("Invalid version requirement")?),
+        ),
+        // Every version is listed, including pre-releases
+        None => (package, None),
+    };
+
+    let index = index::load([name], !args.is_present("json"), true)?;
+    let entry = index.get(name).with_context(|| {
+        format!(
+            "'{}' could not be found. If you know this package exists, try updating the index with $ grill update --index",
+            name
+        )
+    })?;
+
+    let versions = entry
+        .versions
+        .iter()
+        .filter(|(version, _)| req.as_ref().is_none_or(|req| req.matches(version)))
+        .sorted_by(|(v1, _), (v2, _)| v2.cmp(v1))
+        .collect_vec();
+
+    if versions.is_empty() {
+        match req {
+            Some(req) => bail!("No versions of '{}' match {}", name, req),
+            None => bail!("'{}' has no versions", name),
+        }
+    }
+
+    if args.is_present("json") {
+        let versions: Vec<_> = versions
+            .iter()
+            .map(|(version, metadata)| {
+                json!({
+                    "version": version.to_string(),
+                    "rev": metadata.rev,
+                    "yanked": metadata.yanked,
+                    "dependencies": metadata.deps,
+                })
+            })
+            .collect();
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "name": name,
+                "url": entry.url,
+                "metadata": entry.metadata,
+                "versions": versions,
+            }))?
+        );
+        return Ok(());
+    }
+
+    let metadata = &entry.metadata;
+    print_field("Package", name);
+    if !metadata.description.is_empty() {
+        print_field("Description", &metadata.description);
+    }
+    print_field("Source", entry.url.as_str());
+    if let Some(repository) = &metadata.repository {
+        print_field("Repository", repository.as_str());
+    }
+    if let Some(homepage) = &metadata.homepage {
+        print_field("Homepage", homepage.as_str());
+    }
+    if let Some(license) = &metadata.license {
+        print_field("License", license);
+    }
+    if !metadata.authors.is_empty() {
+        print_field("Authors", &metadata.authors.join(", "));
+    }
+    if !metadata.keywords.is_empty() {
+        print_field("Keywords", &metadata.keywords.join(", "));
+    }
+
+    println!();
+    for (version, metadata) in versions {
+        println!(
+            "{:>12} {}{}",
+            style(format!("v{}", version)).bright().cyan(),
+            style(&metadata.rev).dim(),
+            if metadata.yanked {
+                style(" (yanked)").bright().red().to_string()
+            } else {
+                String::new()
+            }
+        );
+
+        for (dep, req) in metadata.deps.iter().sorted_by_key(|(dep, _)| *dep) {
+            println!("{:>12} {} {}", "", dep, req);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_field(name: &str, value: &str) {
+    println!("{:>12} {}", style(name).bright().yellow(), value);
+}
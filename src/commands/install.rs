@@ -4,7 +4,12 @@ use git2::{build::RepoBuilder, FetchOptions, RemoteCallbacks};
 use indicatif::{ProgressBar, ProgressStyle};
 use url::Url;
 
-use crate::{index, manifest::Manifest, paths, prelude::*};
+use crate::{
+    config, index,
+    manifest::Manifest,
+    paths::{self, TempDir},
+    prelude::*,
+};
 
 pub fn cli() -> App {
     App::new("install")
@@ -19,21 +24,24 @@ pub fn cli() -> App {
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
+    if config::get().offline() {
+        bail!("Cannot install packages in offline mode");
+    }
+
     let (url, rev) = if let Some(url) = args.value_of("git") {
         (url.to_string(), None)
     } else {
         let pkg = args.value_of("pkg").unwrap();
 
-        let index = index::parse(true, false)?;
+        let mut index = index::load([pkg], true, false)?;
         if !index.contains_key(pkg) {
             index::update(true, false)?;
+            index = index::load([pkg], false, false)?;
         }
 
         if let Some(entry) = index.get(pkg) {
             let latest = entry
-                .versions
-                .iter()
-                .reduce(|a, b| if a.0 > b.0 { a } else { b })
+                .latest(false)
                 .with_context(|| "No versions found for this package")?;
 
             (entry.url.to_string(), Some(latest.1.rev.clone()))
@@ -44,7 +52,7 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
 
     let url = Url::parse(&url)?;
 
-    rm_rf::remove(paths::tmp())?;
+    let tmp = TempDir::new()?;
 
     let cli_progress = ProgressBar::new(1)
         .with_style(
@@ -67,7 +75,7 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
 
     let mut builder = RepoBuilder::new();
     builder.fetch_options(fo);
-    let repo = match builder.clone(url.as_str(), &paths::tmp()) {
+    let repo = match builder.clone(url.as_str(), tmp.path()) {
         Ok(repo) => repo,
         Err(e) => bail!("Download failed: {}", e),
     };
@@ -89,7 +97,7 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
         .to_string();
     pkg.push_str(&url.path().replace('/', "-").replace(".git", ""));
 
-    let manifest_path = paths::tmp().join(crate::paths::MANIFEST_FILENAME);
+    let manifest_path = tmp.path().join(crate::paths::MANIFEST_FILENAME);
     let mut already_installed_prompt =
         "This package is already installed, do you want to update it?".to_owned();
     let has_manifest = if let Ok(file) = fs::read_to_string(manifest_path) {
@@ -116,7 +124,7 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
 
     let pkg_path = paths::beeflib(&pkg);
     if !pkg_path.exists() {
-        fs::rename(paths::tmp(), &pkg_path)?;
+        fs::rename(tmp.path(), &pkg_path)?;
 
         if !has_manifest
             && dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
@@ -145,7 +153,7 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
         .interact()?
     {
         rm_rf::remove(&pkg_path)?;
-        fs::rename(paths::tmp(), &pkg_path)?;
+        fs::rename(tmp.path(), &pkg_path)?;
 
         println!("{} {}", console::style("Updated").bright().green(), pkg);
     }
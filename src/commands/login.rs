@@ -1,24 +1,44 @@
 use std::fs;
 
-use crate::{paths, prelude::*};
+use crate::{config, paths, prelude::*};
 use dialoguer::{theme::ColorfulTheme, Input};
-use serde::{Deserialize, Serialize};
 
 pub fn cli() -> App {
-    App::new("login").about("Login through the CLI")
+    App::new("login").about("Login through the CLI").arg(
+        Arg::new("registry")
+            .long("registry")
+            .value_name("NAME")
+            .help("The registry to log in to"),
+    )
 }
 
-pub fn exec(_: &ArgMatches) -> Result<()> {
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let (registry, _) = config::get().registry(args.value_of("registry"))?;
+
     let token = Input::<String>::with_theme(&ColorfulTheme::default())
         .with_prompt("paste your token here (found on Account > Settings > Authorization)\n")
         .interact()?;
 
-    fs::write(paths::token(), token)?;
+    fs::write(paths::token(&registry), token)?;
 
     Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ErrorBody {
-    pub message: String,
+/// Read the access token for a registry. Returns `None` and
+/// tells the user to log in if there is no token.
+pub fn read_token(registry: &str) -> Result<Option<String>> {
+    let token_path = paths::token(registry);
+    if !token_path.exists() {
+        log::info!(
+            "You have to log in to use this command. Run `grill login{}` first.",
+            if registry == config::DEFAULT_REGISTRY {
+                String::new()
+            } else {
+                format!(" --registry {}", registry)
+            }
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(fs::read_to_string(token_path)?))
 }
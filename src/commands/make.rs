@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use crate::prelude::*;
+use crate::{ops::MakeOptions, prelude::*};
 
 pub fn cli() -> App {
     App::new("make")
@@ -13,9 +13,35 @@ pub fn cli() -> App {
                 .help("Path to the workspace"),
         )
         .arg(Arg::new("quiet").long("quiet").short('q'))
+        .arg(
+            Arg::new("refresh-index")
+                .long("refresh-index")
+                .help("Update the index even if the cached index is still fresh")
+                .conflicts_with_all(&["offline", "frozen"]),
+        )
+        .arg(
+            Arg::new("jobs")
+                .long("jobs")
+                .short('j')
+                .value_name("N")
+                .help("Number of packages to fetch in parallel, defaults to the number of CPUs"),
+        )
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
     let path = PathBuf::from(args.value_of("path").unwrap());
-    crate::ops::make(&path, args.is_present("quiet"))
+    let jobs = args
+        .value_of("jobs")
+        .map(str::parse)
+        .transpose()
+        .context("Invalid number of jobs")?;
+    crate::ops::make(
+        &path,
+        &MakeOptions {
+            quiet: args.is_present("quiet"),
+            refresh_index: args.is_present("refresh-index"),
+            jobs,
+            locked: super::locked(args),
+        },
+    )
 }
@@ -55,7 +55,7 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
     fs::create_dir(path.join("src"))?;
 
     crate::ops::init::init(path, &name)?;
-    crate::ops::make::make(path, false)?;
+    crate::ops::make::make(path, &Default::default())?;
 
     match project_type {
         ProjectType::Binary => {
@@ -7,9 +7,8 @@ use semver::VersionReq;
 use serde_json::json;
 
 use crate::{
-    index,
+    config, index,
     manifest::{self, Manifest},
-    paths,
     prelude::*,
 };
 
@@ -22,17 +21,35 @@ pub fn cli() -> App {
                 .value_name("COMMIT_HASH")
                 .help("The commit hash to publish"),
         )
+        .arg(
+            Arg::new("registry")
+                .long("registry")
+                .value_name("NAME")
+                .help("The registry to publish to"),
+        )
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
-    let token_path = paths::token();
-    if !token_path.exists() {
-        log::info!("You have to log in to use this command. Run `grill login` first.");
-        return Ok(());
-    }
+    let (registry_name, registry) = config::get().registry(args.value_of("registry"))?;
 
-    let access_token = fs::read_to_string(token_path)?;
+    let access_token = match super::login::read_token(&registry_name)? {
+        Some(token) => token,
+        None => return Ok(()),
+    };
     let manifest = Manifest::from_pkg(".")?;
+
+    for warning in manifest.package.warnings() {
+        log::warn!("{}", warning);
+    }
+
+    let problems = manifest.package.validate(".");
+    if !problems.is_empty() {
+        bail!(
+            "The package can't be published:\n    {}",
+            problems.join("\n    ")
+        );
+    }
+
     let package = manifest.package.name.clone();
     let version = manifest.package.version.to_string();
 
@@ -59,6 +76,11 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
         .ends_with("-dirty");
 
     println!("{:>12} {}", style("Package").bright().yellow(), package);
+    println!(
+        "{:>12} {}",
+        style("Registry").bright().yellow(),
+        registry_name
+    );
     println!("{:>12} {}", style("Version").bright().yellow(), version);
     println!("{:>12} {}", style("Commit").bright().yellow(), style(&rev));
 
@@ -92,7 +114,7 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
     {
         println!();
         index::update(true, true)?;
-        let index = index::parse(false, false)?;
+        let index = index::parse_registry(&registry_name)?;
 
         let mut deps: HashMap<String, VersionReq> = manifest
             .simple_deps()
@@ -110,6 +132,14 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
             }
         }
 
+        let readme = manifest
+            .package
+            .readme
+            .as_ref()
+            .map(fs::read_to_string)
+            .transpose()
+            .context("Failed to read readme")?;
+
         let mut body = json!({
             "access_token": access_token,
             "package": package,
@@ -117,7 +147,14 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
                 "version": version,
                 "revision": rev,
                 "dependencies": deps,
-                "description": manifest.package.description
+                "description": manifest.package.description,
+                "authors": manifest.package.authors,
+                "license": manifest.package.license,
+                "repository": manifest.package.repository,
+                "homepage": manifest.package.homepage,
+                "keywords": manifest.package.keywords,
+                "categories": manifest.package.categories,
+                "readme": readme,
             })
         });
 
@@ -145,19 +182,7 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
             );
         }
 
-        let res = crate::webapi::grill("/publish", &body)?;
-
-        if let Err(err) = res.error_for_status_ref() {
-            let body = res
-                .json::<serde_json::Map<String, serde_json::Value>>()
-                .unwrap();
-            let server_err = if body.contains_key("statusCode") {
-                anyhow!("{} (status: {})", body["message"], body["statusCode"])
-            } else {
-                anyhow!("{}", body["message"])
-            };
-            return Err(server_err).context(err);
-        }
+        crate::webapi::grill(&registry_name, &registry, "/publish", &body)?;
 
         println!(
             "   {} {} {}{}",
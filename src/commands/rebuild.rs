@@ -25,10 +25,11 @@ pub fn cli() -> App {
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
     let quiet = args.is_present("quiet");
+    let locked = super::locked(args);
 
     if args.is_present("all") {
         for path in fs::read_dir(paths::pkgs("."))? {
-            rebuild(&path?.path(), quiet)?;
+            rebuild(&path?.path(), locked, quiet)?;
         }
     } else if let Some(mut pkgs) = args.values_of("pkgs") {
         for dir in fs::read_dir(paths::pkgs("."))? {
@@ -38,15 +39,15 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
             let (pkg, _) = ident.rsplit_once('-').context("Invalid file name")?;
 
             if pkgs.any(|v| v == ident || v == pkg) {
-                rebuild(&dir.path(), quiet)?;
+                rebuild(&dir.path(), locked, quiet)?;
             }
         }
     }
 
-    rebuild(Path::new("."), quiet)
+    rebuild(Path::new("."), locked, quiet)
 }
 
-fn rebuild(path: &Path, quiet: bool) -> Result<()> {
+fn rebuild(path: &Path, locked: bool, quiet: bool) -> Result<()> {
     let file_name = if path.ends_with(".") {
         Cow::Owned(
             std::env::current_dir()?
@@ -75,7 +76,7 @@ fn rebuild(path: &Path, quiet: bool) -> Result<()> {
         None
     };
 
-    crate::ops::rebuild(path, spinner.as_ref())?;
+    crate::ops::rebuild(path, locked, spinner.as_ref())?;
 
     if let Some(spinner) = spinner {
         spinner.set_prefix(console::style("Finished").bright().green().to_string());
@@ -0,0 +1,202 @@
+use console::style;
+use semver::Version;
+use serde_json::json;
+
+use crate::{
+    index::{self, IndexEntry},
+    prelude::*,
+};
+
+pub fn cli() -> App {
+    App::new("search")
+        .about("Search for packages in the index")
+        .arg(Arg::new("query").value_name("QUERY").required(true))
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .value_name("N")
+                .default_value("10")
+                .help("Maximum number of results"),
+        )
+        .arg(Arg::new("json").long("json").help("Output results as JSON"))
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let query = args.value_of("query").unwrap();
+    let limit: usize = args
+        .value_of("limit")
+        .unwrap()
+        .parse()
+        .context("Invalid limit")?;
+
+    let index = index::parse(!args.is_present("json"), true)?;
+
+    let mut results: Vec<(u32, &String, &IndexEntry)> = index
+        .iter()
+        .filter_map(|(name, entry)| score(query, name, entry).map(|score| (score, name, entry)))
+        .collect();
+    results.sort_by(|(s1, n1, _), (s2, n2, _)| s2.cmp(s1).then(n1.cmp(n2)));
+    let total = results.len();
+    results.truncate(limit);
+
+    if args.is_present("json") {
+        let results: Vec<_> = results
+            .iter()
+            .map(|(_, name, entry)| {
+                json!({
+                    "name": name,
+                    "version": latest_version(entry).map(Version::to_string),
+                    "description": entry.metadata.description,
+                    "keywords": entry.metadata.keywords,
+                    "url": entry.url,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+
+    if results.is_empty() {
+        println!("No packages found matching '{}'", query);
+        return Ok(());
+    }
+
+    let width = results
+        .iter()
+        .map(|(_, name, entry)| name.len() + display_version(entry).len())
+        .max()
+        .unwrap_or_default();
+
+    for (_, name, entry) in results.iter() {
+        let version = display_version(entry);
+        println!(
+            "{} {}{}  {}",
+            style(name).bright().cyan(),
+            style(&version).dim(),
+            " ".repeat(width - name.len() - version.len()),
+            entry.metadata.description
+        );
+    }
+
+    if total > limit {
+        println!(
+            "\n... and {} more packages (use --limit to see more)",
+            total - limit
+        );
+    }
+
+    Ok(())
+}
+
+fn display_version(entry: &IndexEntry) -> String {
+    latest_version(entry)
+        .map(|version| format!("v{}", version))
+        .unwrap_or_default()
+}
+
+/// The latest stable version, or the latest pre-release
+/// if the package has no stable versions.
+fn latest_version(entry: &IndexEntry) -> Option<&Version> {
+    entry
+        .latest(false)
+        .or_else(|| entry.latest(true))
+        .map(|(version, _)| version)
+}
+
+/// Score how well a package matches the query, or `None` if it doesn't match.
+/// Every word in the query has to match the name, keywords or description.
+fn score(query: &str, name: &str, entry: &IndexEntry) -> Option<u32> {
+    if query.trim().is_empty() {
+        return None;
+    }
+
+    let name = name.to_lowercase();
+    let description = entry.metadata.description.to_lowercase();
+
+    query
+        .split_whitespace()
+        .map(|term| {
+            let term = term.to_lowercase();
+            if name == term {
+                Some(100)
+            } else if name.starts_with(&term) {
+                Some(80)
+            } else if name.contains(&term) {
+                Some(60)
+            } else if entry
+                .metadata
+                .keywords
+                .iter()
+                .any(|keyword| keyword.to_lowercase() == term)
+            {
+                Some(50)
+            } else if is_subsequence(&term, &name) {
+                Some(40)
+            } else if description.contains(&term)
+                || entry
+                    .metadata
+                    .keywords
+                    .iter()
+                    .any(|keyword| keyword.to_lowercase().contains(&term))
+            {
+                Some(20)
+            } else {
+                None
+            }
+        })
+        .sum()
+}
+
+/// Returns true if all characters of `needle` appear in order in `haystack`.
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use url::Url;
+
+    use crate::index::{IndexEntry, PackageMetadata};
+
+    fn entry(description: &str, keywords: &[&str]) -> IndexEntry {
+        IndexEntry {
+            url: Url::parse("http://localhost").unwrap(),
+            versions: HashMap::new(),
+            metadata: PackageMetadata {
+                description: description.to_owned(),
+                keywords: keywords.iter().map(|k| k.to_string()).collect(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn score() {
+        let entry = entry("Bindings for the OpenGL API", &["graphics"]);
+        let score = |query| super::score(query, "OpenGL-Beef", &entry);
+
+        assert_eq!(score("opengl-beef"), Some(100));
+        assert_eq!(score("open"), Some(80));
+        assert_eq!(score("beef"), Some(60));
+        assert_eq!(score("Graphics"), Some(50));
+        assert_eq!(score("oglb"), Some(40));
+        assert_eq!(score("api"), Some(20));
+        assert_eq!(score("open graphics"), Some(130));
+
+        assert_eq!(score("vulkan"), None);
+        assert_eq!(score("open vulkan"), None);
+        assert_eq!(score(""), None);
+        assert_eq!(score("   "), None);
+    }
+
+    #[test]
+    fn is_subsequence() {
+        assert!(super::is_subsequence("ogl", "opengl"));
+        assert!(super::is_subsequence("", "opengl"));
+        assert!(!super::is_subsequence("glo", "opengl"));
+        assert!(!super::is_subsequence("openglx", "opengl"));
+    }
+}
@@ -1,9 +1,10 @@
 use std::path::Path;
 
 use crate::{
-    index,
-    lock::{self, Lock},
+    config, index,
+    lock::{self, Keep, Lock, Source},
     prelude::*,
+    resolver::{Resolution, Strategy},
 };
 use console::style;
 
@@ -11,6 +12,12 @@ pub fn cli() -> App {
     App::new("update")
         .about("Update dependencies to the latest version")
         .arg(Arg::new("quiet").long("quiet").short('q'))
+        .arg(
+            Arg::new("packages")
+                .value_name("PACKAGE")
+                .multiple_values(true)
+                .help("Only update these packages, and move git branches to their latest commit"),
+        )
         .arg(
             Arg::new("grill")
                 .long("grill")
@@ -22,6 +29,12 @@ pub fn cli() -> App {
                 .long("index")
                 .help("Update the package index"), // .conflicts_with("grill"),
         )
+        .arg(
+            Arg::new("minimal-versions")
+                .long("minimal-versions")
+                .help("Use the lowest versions that satisfy the requirements")
+                .conflicts_with_all(&["grill", "index", "packages"]),
+        )
 }
 
 pub fn exec(args: &ArgMatches) -> Result<()> {
@@ -30,7 +43,9 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
     } else if args.is_present("index") {
         index::update(!args.is_present("quiet"), false)
     } else {
-        index::update(!args.is_present("quiet"), false)?;
+        if !config::get().offline() {
+            index::update(!args.is_present("quiet"), false)?;
+        }
 
         let lock_path = Path::new(".").join(crate::paths::LOCK_FILENAME);
         let old_lock = if lock_path.exists() {
@@ -39,13 +54,49 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
             None
         };
 
-        let lock = lock::generate(Path::new("."), true, false)?;
+        let packages: Vec<String> = args
+            .get_many::<String>("packages")
+            .map(|packages| packages.cloned().collect())
+            .unwrap_or_default();
+        for package in packages.iter() {
+            if !old_lock
+                .iter()
+                .flat_map(|lock| lock.packages.iter())
+                .any(|locked| &locked.name == package)
+            {
+                bail!(
+                    "Package '{}' is not in {}",
+                    package,
+                    crate::paths::LOCK_FILENAME
+                );
+            }
+        }
+
+        let keep = if packages.is_empty() {
+            Keep::Nothing
+        } else {
+            Keep::AllExcept(&packages)
+        };
+        let strategy = if args.is_present("minimal-versions") {
+            Strategy::Lowest
+        } else {
+            Strategy::Highest
+        };
+        let lock = lock::generate_with(
+            Path::new("."),
+            true,
+            super::locked(args),
+            keep,
+            strategy,
+            |missing| crate::ops::fetch_source(Path::new("."), missing),
+        )?;
 
         if !args.is_present("quiet") {
             if let Some(old_lock) = old_lock {
-                print_altered_deps(&old_lock, &lock);
+                print_altered_deps(&old_lock.versions(), &lock.versions());
+                print_altered_revs(&old_lock, &lock);
             } else {
-                for (dep, versions) in lock {
+                for (dep, versions) in lock.versions() {
                     for version in versions {
                         println!(
                             "{:>12} {} v{}",
@@ -62,7 +113,31 @@ pub fn exec(args: &ArgMatches) -> Result<()> {
     }
 }
 
-fn print_altered_deps(old_lock: &Lock, new_lock: &Lock) {
+/// Prints the git packages that were moved to another commit.
+fn print_altered_revs(old_lock: &Lock, new_lock: &Lock) {
+    for package in new_lock.packages_from(Source::Git) {
+        if let Some(old_rev) = old_lock
+            .packages_from(Source::Git)
+            .find(|old| old.name == package.name && old.url == package.url)
+            .and_then(|old| old.rev.as_ref())
+            .filter(|&old_rev| Some(old_rev) != package.rev.as_ref())
+        {
+            println!(
+                "{:>12} {} {} -> {}",
+                style("Updated").bright().green(),
+                package.name,
+                short_rev(old_rev),
+                short_rev(package.rev.as_deref().unwrap_or_default())
+            );
+        }
+    }
+}
+
+fn short_rev(rev: &str) -> &str {
+    &rev[..rev.len().min(7)]
+}
+
+fn print_altered_deps(old_lock: &Resolution, new_lock: &Resolution) {
     for (dep, versions) in new_lock {
         for version in versions {
             if old_lock
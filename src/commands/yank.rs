@@ -0,0 +1,67 @@
+use console::style;
+use semver::Version;
+use serde_json::json;
+
+use crate::{config, prelude::*};
+
+pub fn cli() -> App {
+    App::new("yank")
+        .about("Prevent a published version from being used in new resolutions")
+        .arg(
+            Arg::new("package")
+                .value_name("PACKAGE@VERSION")
+                .required(true),
+        )
+        .arg(
+            Arg::new("undo")
+                .long("undo")
+                .help("Allow the version to be used again"),
+        )
+        .arg(
+            Arg::new("registry")
+                .long("registry")
+                .value_name("NAME")
+                .help("The registry the package is published to"),
+        )
+}
+
+pub fn exec(args: &ArgMatches) -> Result<()> {
+    let (package, version) = args
+        .value_of("package")
+        .unwrap()
+        .split_once('@')
+        .context("Expected a package and version, e.g. MyPackage@1.0.0")?;
+    let version = Version::parse(version).context("Invalid version")?;
+    let undo = args.is_present("undo");
+
+    let (registry_name, registry) = config::get().registry(args.value_of("registry"))?;
+    let access_token = match super::login::read_token(&registry_name)? {
+        Some(token) => token,
+        None => return Ok(()),
+    };
+
+    crate::webapi::grill(
+        &registry_name,
+        &registry,
+        "/yank",
+        &json!({
+            "access_token": access_token,
+            "package": package,
+            "version": version.to_string(),
+            "yanked": !undo,
+        }),
+    )?;
+
+    println!(
+        "{:>12} {} v{}",
+        if undo {
+            style("Unyanked").bright().green()
+        } else {
+            style("Yanked").bright().yellow()
+        },
+        package,
+        version
+    );
+
+    Ok(())
+}
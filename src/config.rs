@@ -0,0 +1,210 @@
+use std::{collections::HashMap, fs, path::Path, sync::OnceLock, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{cache::CacheMode, paths};
+
+pub const DEFAULT_REGISTRY: &str = "grill";
+const DEFAULT_INDEX: &str = "https://github.com/RogueMacro/grill-index";
+const DEFAULT_API: &str = "http://grillpm.vercel.app/api";
+const DEFAULT_INDEX_TTL: u64 = 10 * 60;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// User configuration, read from `~/.grill/config.toml` and
+/// optionally overridden by `.grill/config.toml` in the workspace.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct Config {
+    /// The registry used by commands when none is specified.
+    pub registry: Option<String>,
+    #[serde(default)]
+    pub registries: HashMap<String, Registry>,
+    /// Only use the cached index and packages already on disk.
+    pub offline: Option<bool>,
+    /// Seconds before a cached index is considered outdated by `make`.
+    pub index_ttl: Option<u64>,
+    /// How packages from the global cache are put into workspaces.
+    pub cache: Option<CacheMode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct Registry {
+    /// Git url of the index repository. Can be a `file://` url.
+    pub index: Url,
+    /// Base url of the web API used for publishing.
+    pub api: Option<Url>,
+}
+
+impl Registry {
+    /// The API url of the registry called `name`.
+    pub fn api(&self, name: &str) -> Result<&str> {
+        self.api
+            .as_ref()
+            .map(|api| api.as_str().trim_end_matches('/'))
+            .ok_or_else(|| anyhow!("Registry '{}' has no API configured", name))
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            index: Url::parse(DEFAULT_INDEX).unwrap(),
+            api: Some(Url::parse(DEFAULT_API).unwrap()),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the global configuration and merges the first workspace
+    /// configuration found in `ws` or any of its parents.
+    pub fn load(ws: &Path) -> Result<Config> {
+        let global_path = paths::config();
+        let mut config = Self::from_file(&global_path)?;
+
+        if let Some(ws_path) = ws
+            .ancestors()
+            .map(paths::ws_config)
+            .find(|path| path.exists() && path != &global_path)
+        {
+            config.merge(Self::from_file(&ws_path)?);
+        }
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        toml::from_str(
+            &fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config at '{}'", path.display()))?,
+        )
+        .with_context(|| format!("Failed to parse config at '{}'", path.display()))
+    }
+
+    fn merge(&mut self, other: Config) {
+        if other.registry.is_some() {
+            self.registry = other.registry;
+        }
+        self.registries.extend(other.registries);
+        if other.offline.is_some() {
+            self.offline = other.offline;
+        }
+        if other.index_ttl.is_some() {
+            self.index_ttl = other.index_ttl;
+        }
+        if other.cache.is_some() {
+            self.cache = other.cache;
+        }
+    }
+
+    pub fn offline(&self) -> bool {
+        self.offline.unwrap_or_default()
+    }
+
+    pub fn index_ttl(&self) -> Duration {
+        Duration::from_secs(self.index_ttl.unwrap_or(DEFAULT_INDEX_TTL))
+    }
+
+    pub fn cache_mode(&self) -> CacheMode {
+        self.cache.unwrap_or_default()
+    }
+
+    pub fn default_registry(&self) -> &str {
+        self.registry.as_deref().unwrap_or(DEFAULT_REGISTRY)
+    }
+
+    /// Get a registry by name, or the default registry.
+    pub fn registry(&self, name: Option<&str>) -> Result<(String, Registry)> {
+        let name = name.unwrap_or_else(|| self.default_registry());
+        self.registries()
+            .into_iter()
+            .find(|(registry, _)| registry == name)
+            .ok_or_else(|| anyhow!("No registry named '{}' is configured", name))
+    }
+
+    /// All registries to use, with the default registry first. The public
+    /// grill registry is only used when no registries are configured.
+    pub fn registries(&self) -> Vec<(String, Registry)> {
+        if self.registries.is_empty() {
+            return vec![(DEFAULT_REGISTRY.to_owned(), Registry::default())];
+        }
+
+        let default = self.default_registry();
+        let mut registries: Vec<(String, Registry)> = self
+            .registries
+            .iter()
+            .map(|(name, registry)| (name.clone(), registry.clone()))
+            .collect();
+        registries.sort_by(|(a, _), (b, _)| (a != default).cmp(&(b != default)).then(a.cmp(b)));
+        registries
+    }
+}
+
+/// Load the configuration for the current directory.
+/// Should be called once at startup.
+pub fn init(offline: bool) -> Result<()> {
+    let mut config = Config::load(&std::env::current_dir()?)?;
+    if offline {
+        config.offline = Some(true);
+    }
+    CONFIG
+        .set(config)
+        .map_err(|_| anyhow!("Config is already initialized"))
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        std::env::current_dir()
+            .map_err(anyhow::Error::from)
+            .and_then(|dir| Config::load(&dir))
+            .unwrap_or_else(|err| {
+                log::warn!("Failed to load config: {}", err);
+                Config::default()
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, DEFAULT_REGISTRY};
+
+    #[test]
+    fn registries() {
+        let names = |config: &Config| -> Vec<String> {
+            config
+                .registries()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+
+        let config = Config::default();
+        assert_eq!(names(&config), [DEFAULT_REGISTRY]);
+        assert!(config.registry(Some(DEFAULT_REGISTRY)).is_ok());
+
+        // Configured registries replace the public one
+        let mut config: Config = toml::from_str(
+            r#"
+            Registry = "internal"
+            [Registries.internal]
+            Index = "file:///internal"
+            [Registries.other]
+            Index = "file:///other"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(names(&config), ["internal", "other"]);
+        assert!(config.registry(Some(DEFAULT_REGISTRY)).is_err());
+
+        config.registry = Some(String::from("other"));
+        assert_eq!(names(&config), ["other", "internal"]);
+        assert_eq!(config.registry(None).unwrap().0, "other");
+    }
+}
@@ -1,13 +1,21 @@
-use std::{collections::HashMap, fs, time::Duration};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::{Context, Result};
-use git2::Repository;
+use anyhow::{bail, Context, Result};
+use git2::{Oid, Repository};
 use indicatif::ProgressBar;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::paths;
+use crate::{
+    config::{self, Registry},
+    paths,
+};
 
 pub type Index = HashMap<String, IndexEntry>;
 
@@ -15,35 +23,97 @@ pub type Index = HashMap<String, IndexEntry>;
 pub struct IndexEntry {
     pub url: Url,
     pub versions: HashMap<Version, VersionMetadata>,
+    #[serde(flatten)]
+    pub metadata: PackageMetadata,
+}
+
+/// Metadata of the latest published version of a package.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PackageMetadata {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<Url>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+}
+
+impl IndexEntry {
+    /// The latest version that is not yanked. Pre-releases are
+    /// only considered if `allow_prerelease` is set.
+    pub fn latest(&self, allow_prerelease: bool) -> Option<(&Version, &VersionMetadata)> {
+        self.versions
+            .iter()
+            .filter(|(version, metadata)| {
+                !metadata.yanked && (allow_prerelease || version.pre.is_empty())
+            })
+            .max_by_key(|(version, _)| *version)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct VersionMetadata {
     pub rev: String,
     pub deps: HashMap<String, VersionReq>,
+    /// Yanked versions are not used for new resolutions,
+    /// but are still allowed if they are already locked.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub yanked: bool,
+}
+
+/// When each registry's index was last updated, and to which commit.
+pub type State = HashMap<String, RegistryState>;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct RegistryState {
+    /// Seconds since the unix epoch.
+    pub updated: u64,
+    pub commit: String,
 }
 
-// pub fn is_outdated() -> Result<bool> {
-//     let response = crate::webapi::github(
-//         "/graphql",
-//         &"
-//         query {
-//             repository(owner:\"roguemacro\", name:\"grill-index\") {
-//                 url
-//             }
-//         }
-//         ",
-//     )?;
+pub fn state() -> State {
+    fs::read_to_string(paths::index_state())
+        .ok()
+        .and_then(|state| toml::from_str(&state).ok())
+        .unwrap_or_default()
+}
 
-//     println!("{}: {}", response.status(), response.text()?);
+/// Returns true if every registry has been updated within the configured TTL.
+pub fn is_fresh() -> bool {
+    let state = state();
+    let ttl = config::get().index_ttl().as_secs();
+    let now = unix_now();
 
-//     Ok(false)
-// }
+    config::get().registries().iter().all(|(name, _)| {
+        state
+            .get(name)
+            .is_some_and(|registry| now.saturating_sub(registry.updated) < ttl)
+    })
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
 
 pub fn update(with_spinner: bool, clear_after: bool) -> Result<()> {
     log::trace!("Updating index");
 
-    rm_rf::ensure_removed(paths::tmp()).context("Failed to remove tmp folder")?;
+    if config::get().offline() {
+        bail!("Cannot update the index in offline mode");
+    }
+    let _lock = paths::lock_home()?;
 
     let spinner = ProgressBar::new_spinner();
     if with_spinner {
@@ -54,10 +124,31 @@ pub fn update(with_spinner: bool, clear_after: bool) -> Result<()> {
         spinner.enable_steady_tick(Duration::from_millis(100));
     }
 
-    Repository::clone("https://github.com/RogueMacro/grill-index", paths::tmp())
-        .context("Failed to clone repository")?;
-    fs::copy(paths::tmp().join("index.toml"), paths::index())
-        .context("Failed to move index file")?;
+    let mut state = state();
+    for (i, (name, registry)) in config::get().registries().into_iter().enumerate() {
+        let commit = match update_registry(&name, &registry) {
+            Ok(commit) => commit,
+            // Only the default registry is required
+            Err(err) if i > 0 => {
+                log::warn!("Failed to update registry '{}': {:#}", name, err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        state.insert(
+            name,
+            RegistryState {
+                updated: unix_now(),
+                commit: commit.to_string(),
+            },
+        );
+    }
+
+    fs::write(
+        paths::index_state(),
+        toml::to_string(&state).context("Failed to serialize index state")?,
+    )
+    .context("Failed to write index state")?;
 
     if with_spinner {
         if clear_after {
@@ -73,12 +164,325 @@ pub fn update(with_spinner: bool, clear_after: bool) -> Result<()> {
     Ok(())
 }
 
+/// Fetches the index of a registry into a persistent clone, only
+/// downloading new objects when the remote head has moved.
+/// Returns the commit the index is at.
+fn update_registry(name: &str, registry: &Registry) -> Result<Oid> {
+    log::trace!("Updating registry {} from {}", name, registry.index);
+
+    let path = paths::index(name);
+    let repo = match Repository::open(&path) {
+        Ok(repo) => repo,
+        Err(_) => {
+            rm_rf::ensure_removed(&path).context("Failed to remove old index")?;
+            let repo = Repository::clone(registry.index.as_str(), &path)
+                .with_context(|| format!("Failed to clone index of registry '{}'", name))?;
+            return repo
+                .head()?
+                .target()
+                .context("Index repository has no HEAD");
+        }
+    };
+
+    repo.remote_set_url("origin", registry.index.as_str())?;
+    let mut remote = repo.find_remote("origin")?;
+    remote
+        .connect(git2::Direction::Fetch)
+        .with_context(|| format!("Failed to connect to index of registry '{}'", name))?;
+    let remote_head = remote
+        .list()?
+        .iter()
+        .find(|head| head.name() == "HEAD")
+        .map(|head| head.oid())
+        .context("Remote index has no HEAD")?;
+    remote.disconnect()?;
+
+    if repo.head().ok().and_then(|head| head.target()) == Some(remote_head) {
+        log::trace!("Index of {} is up to date", name);
+        return Ok(remote_head);
+    }
+
+    remote
+        .fetch::<&str>(&[], None, None)
+        .with_context(|| format!("Failed to fetch index of registry '{}'", name))?;
+    let commit = repo.find_commit(remote_head)?;
+    repo.reset(commit.as_object(), git2::ResetType::Hard, None)
+        .context("Failed to fast-forward index")?;
+
+    Ok(remote_head)
+}
+
+/// Parse the indices of all configured registries into one.
+/// Packages in the default registry take precedence.
 pub fn parse(with_spinner: bool, clear_after: bool) -> Result<Index> {
     log::trace!("Parsing index");
 
-    let path = paths::index();
-    toml::from_str::<Index>(&fs::read_to_string(&path)?).or_else(|err| {
+    with_update(with_spinner, clear_after, read_all)
+}
+
+/// Load only the entries of the given packages and their transitive
+/// dependencies. Sparse indices are read lazily, so this avoids parsing
+/// the whole index.
+pub fn load<I, S>(pkgs: I, with_spinner: bool, clear_after: bool) -> Result<Index>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    log::trace!("Loading index entries");
+
+    let pkgs: Vec<String> = pkgs
+        .into_iter()
+        .map(|pkg| pkg.as_ref().to_owned())
+        .collect();
+    with_update(with_spinner, clear_after, || read_reachable(&pkgs))
+}
+
+/// Parse the index of a single registry.
+pub fn parse_registry(name: &str) -> Result<Index> {
+    RegistryIndex::open(name)?.into_index()
+}
+
+/// Path of a package's entry in a sparse index, relative to the index root.
+/// Entries are sharded by name: `1/a`, `2/ab`, `3/a/abc` and `ab/cd/abcd`.
+pub fn sparse_path(pkg: &str) -> PathBuf {
+    let lower = pkg.to_lowercase();
+    let mut chars = lower.chars();
+    let mut path = match lower.chars().count() {
+        1 => PathBuf::from("1"),
+        2 => PathBuf::from("2"),
+        3 => Path::new("3").join(chars.next().unwrap().to_string()),
+        _ => Path::new(&chars.by_ref().take(2).collect::<String>())
+            .join(chars.take(2).collect::<String>()),
+    };
+    path.push(pkg);
+    path
+}
+
+fn with_update<F>(with_spinner: bool, clear_after: bool, read: F) -> Result<Index>
+where
+    F: Fn() -> Result<Index>,
+{
+    read().or_else(|err| {
+        if config::get().offline() {
+            return Err(err.context("No cached index available, run without --offline to fetch it"));
+        }
+
         update(with_spinner, clear_after)?;
-        toml::from_str::<Index>(&fs::read_to_string(&path)?).context(err)
+        read().context(err)
     })
 }
+
+fn read_all() -> Result<Index> {
+    let mut index = Index::new();
+    for (i, (name, _)) in config::get().registries().into_iter().enumerate() {
+        let registry = match parse_registry(&name) {
+            Ok(registry) => registry,
+            Err(err) if i > 0 => {
+                log::warn!("Skipping registry '{}': {:#}", name, err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        for (pkg, entry) in registry {
+            index.entry(pkg).or_insert(entry);
+        }
+    }
+
+    Ok(index)
+}
+
+fn read_reachable(pkgs: &[String]) -> Result<Index> {
+    let mut registries = config::get()
+        .registries()
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (name, _))| match RegistryIndex::open(name) {
+            Err(err) if i > 0 => {
+                log::warn!("Skipping registry '{}': {:#}", name, err);
+                None
+            }
+            result => Some(result),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut index = Index::new();
+    let mut queue = pkgs.to_vec();
+    while let Some(pkg) = queue.pop() {
+        if index.contains_key(&pkg) {
+            continue;
+        }
+
+        for registry in registries.iter_mut() {
+            if let Some(entry) = registry.take(&pkg)? {
+                queue.extend(
+                    entry
+                        .versions
+                        .values()
+                        .flat_map(|metadata| metadata.deps.keys())
+                        .filter(|dep| !index.contains_key(*dep))
+                        .cloned(),
+                );
+                index.insert(pkg, entry);
+                break;
+            }
+        }
+    }
+
+    Ok(index)
+}
+
+/// The index of a single registry, either as one `index.toml` file
+/// or as a sparse directory tree with one file per package.
+enum RegistryIndex {
+    Monolithic(Index),
+    Sparse { name: String, root: PathBuf },
+}
+
+impl RegistryIndex {
+    fn open(name: &str) -> Result<RegistryIndex> {
+        let root = paths::index(name);
+        let monolithic_path = root.join(paths::INDEX_FILENAME);
+        if monolithic_path.exists() {
+            let index = toml::from_str(
+                &fs::read_to_string(&monolithic_path)
+                    .with_context(|| format!("Failed to read index of registry '{}'", name))?,
+            )
+            .with_context(|| format!("Failed to parse index of registry '{}'", name))?;
+            Ok(RegistryIndex::Monolithic(index))
+        } else if root.join(".git").exists() {
+            Ok(RegistryIndex::Sparse {
+                name: name.to_owned(),
+                root,
+            })
+        } else {
+            bail!("The index of registry '{}' has not been fetched", name)
+        }
+    }
+
+    /// Remove an entry from the index, reading it from disk if the index is sparse.
+    fn take(&mut self, pkg: &str) -> Result<Option<IndexEntry>> {
+        match self {
+            RegistryIndex::Monolithic(index) => Ok(index.remove(pkg)),
+            RegistryIndex::Sparse { name, root } => {
+                let path = root.join(sparse_path(pkg));
+                if !path.exists() {
+                    return Ok(None);
+                }
+
+                read_entry(&path)
+                    .with_context(|| format!("Failed to read '{}' from registry '{}'", pkg, name))
+                    .map(Some)
+            }
+        }
+    }
+
+    fn into_index(self) -> Result<Index> {
+        match self {
+            RegistryIndex::Monolithic(index) => Ok(index),
+            RegistryIndex::Sparse { name, root } => {
+                let mut index = Index::new();
+                read_sparse_dir(&root, &root, &mut index)
+                    .with_context(|| format!("Failed to read index of registry '{}'", name))?;
+                Ok(index)
+            }
+        }
+    }
+}
+
+/// Reads the entries below `dir`. Files that aren't where [`sparse_path`]
+/// puts an entry of that name (README, backups etc.) are skipped.
+fn read_sparse_dir(root: &Path, dir: &Path, index: &mut Index) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.is_dir() {
+            read_sparse_dir(root, &path, index)?;
+        } else if path.strip_prefix(root)? == sparse_path(&file_name) {
+            index.insert(file_name, read_entry(&path)?);
+        } else {
+            log::debug!("Skipping {} in sparse index", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn read_entry(path: &Path) -> Result<IndexEntry> {
+    toml::from_str(&fs::read_to_string(path)?)
+        .with_context(|| format!("Failed to parse index entry '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use maplit::hashmap;
+    use semver::Version;
+
+    use super::{IndexEntry, VersionMetadata};
+
+    #[test]
+    fn sparse_path() {
+        assert_eq!(super::sparse_path("a"), Path::new("1/a"));
+        assert_eq!(super::sparse_path("Ab"), Path::new("2/Ab"));
+        assert_eq!(super::sparse_path("abc"), Path::new("3/a/abc"));
+        assert_eq!(super::sparse_path("OpenGL"), Path::new("op/en/OpenGL"));
+    }
+
+    #[test]
+    fn read_sparse_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |path: &str, contents: &str| {
+            let path = root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        let entry = r#"
+            url = "http://localhost/pkg"
+            [versions."1.0.0"]
+            rev = "abc"
+            deps = {}
+        "#;
+
+        write("README.md", "# Index");
+        write("config.json", "{}");
+        write("3/a/abc", entry);
+        write("3/a/abc.toml~", "not toml");
+        write("op/en/OpenGL", entry);
+        write("op/en/Other", entry);
+
+        let mut index = super::Index::new();
+        super::read_sparse_dir(root.path(), root.path(), &mut index).unwrap();
+
+        let mut names: Vec<&String> = index.keys().collect();
+        names.sort();
+        assert_eq!(names, ["OpenGL", "abc"]);
+    }
+
+    #[test]
+    fn latest() {
+        let metadata = |yanked| VersionMetadata {
+            rev: String::new(),
+            deps: Default::default(),
+            yanked,
+        };
+        let entry = IndexEntry {
+            url: url::Url::parse("http://localhost").unwrap(),
+            metadata: Default::default(),
+            versions: hashmap! {
+                Version::parse("1.0.0").unwrap() => metadata(false),
+                Version::parse("1.1.0").unwrap() => metadata(true),
+                Version::parse("1.2.0-beta.1").unwrap() => metadata(false),
+            },
+        };
+
+        let latest = |allow_prerelease| entry.latest(allow_prerelease).unwrap().0.to_string();
+        assert_eq!(latest(false), "1.0.0");
+        assert_eq!(latest(true), "1.2.0-beta.1");
+    }
+}
@@ -1,5 +1,7 @@
 pub mod beef;
+pub mod cache;
 pub mod commands;
+pub mod config;
 pub mod index;
 pub mod lock;
 pub mod log;
@@ -16,7 +18,27 @@ pub fn cli() -> App {
         .about(clap::crate_description!())
         .version(clap::crate_version!())
         .arg(Arg::new("debug").long("debug").global(true))
+        .arg(
+            Arg::new("offline")
+                .long("offline")
+                .global(true)
+                .help("Run without accessing the network"),
+        )
+        .arg(
+            Arg::new("locked")
+                .long("locked")
+                .global(true)
+                .help("Fail instead of updating Package.lock"),
+        )
+        .arg(
+            Arg::new("frozen")
+                .long("frozen")
+                .global(true)
+                .help("Same as --locked and --offline"),
+        )
         .subcommand(commands::add::cli())
+        .subcommand(commands::cache::cli())
+        .subcommand(commands::info::cli())
         .subcommand(commands::init::cli())
         .subcommand(commands::install::cli())
         .subcommand(commands::list::cli())
@@ -26,7 +48,9 @@ pub fn cli() -> App {
         .subcommand(commands::publish::cli())
         .subcommand(commands::purge::cli())
         .subcommand(commands::rebuild::cli())
+        .subcommand(commands::search::cli())
         .subcommand(commands::update::cli())
+        .subcommand(commands::yank::cli())
 }
 
 pub mod prelude {
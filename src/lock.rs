@@ -1,27 +1,179 @@
 use std::{
     collections::{HashMap, HashSet},
-    fs,
-    path::Path,
+    fs, iter,
+    path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result};
-use semver::Version;
+use anyhow::{bail, Context, Result};
+use either::Either;
+use itertools::Itertools;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use url::Url;
 
-use crate::{manifest::Manifest, resolver::resolve};
+use crate::{
+    index::Index,
+    manifest::{GitDependency, Manifest, Patch},
+    resolver::{self, Choices, Options, Resolution, Strategy},
+};
+
+/// Version of the lock file format. Lock files without a version
+/// are from before git and local dependencies were recorded.
+pub const LOCK_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Lock {
+    pub version: u32,
+    #[serde(rename = "Package", default)]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: Version,
+    pub source: Source,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+    /// The commit of indexed and git packages. Indexed packages are locked
+    /// at the revision in the index until they are first fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// The branch or tag of git packages that `rev` was resolved from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Checksum of the contents of indexed packages, recorded when
+    /// they are first fetched. See [`crate::ops::checksum`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Path of local packages, relative to the workspace,
+    /// or of git packages, relative to the repository.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// The locked dependencies, as `name version`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Index,
+    Git,
+    Local,
+}
+
+impl Lock {
+    /// The locked versions of indexed packages.
+    pub fn versions(&self) -> Resolution {
+        let mut versions = Resolution::new();
+        for package in self.packages_from(Source::Index) {
+            versions
+                .entry(package.name.clone())
+                .or_default()
+                .insert(package.version.clone());
+        }
+        versions
+    }
+
+    pub fn packages_from(&self, source: Source) -> impl Iterator<Item = &LockedPackage> {
+        self.packages
+            .iter()
+            .filter(move |package| package.source == source)
+    }
+}
+
+impl From<Resolution> for Lock {
+    /// Lock files without a version only contain the versions of indexed packages.
+    fn from(versions: Resolution) -> Self {
+        let mut packages: Vec<LockedPackage> = versions
+            .into_iter()
+            .flat_map(|(name, versions)| {
+                versions.into_iter().map(move |version| LockedPackage {
+                    name: name.clone(),
+                    version,
+                    source: Source::Index,
+                    url: None,
+                    rev: None,
+                    branch: None,
+                    tag: None,
+                    checksum: None,
+                    path: None,
+                    dependencies: Vec::new(),
+                })
+            })
+            .collect();
+        packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+        Lock {
+            version: 1,
+            packages,
+        }
+    }
+}
 
-pub type Lock = HashMap<String, HashSet<Version>>;
+impl LockedPackage {
+    /// Returns true if both refer to the same package, ignoring dependencies.
+    fn is_same(&self, other: &LockedPackage) -> bool {
+        self.name == other.name
+            && self.version == other.version
+            && self.source == other.source
+            && self.url == other.url
+            && self.rev == other.rev
+            && self.branch == other.branch
+            && self.tag == other.tag
+            && self.path == other.path
+    }
 
+    fn is_root(&self) -> bool {
+        self.source == Source::Local && self.path.as_deref() == Some(Path::new("."))
+    }
+}
+
+/// Reads a lock file. Lock files in an older format are migrated, and
+/// will be rewritten in the current format the next time they are generated.
 pub fn read<P>(path: P) -> Result<Lock>
 where
     P: AsRef<Path>,
 {
-    toml::from_str(&fs::read_to_string(path.as_ref()).with_context(|| {
-        format!(
-            "Failed to read lock file: {}",
-            path.as_ref().to_string_lossy()
-        )
-    })?)
-    .context("Failed to deserialize lock file")
+    let value: toml::Value =
+        toml::from_str(&fs::read_to_string(path.as_ref()).with_context(|| {
+            format!(
+                "Failed to read lock file: {}",
+                path.as_ref().to_string_lossy()
+            )
+        })?)
+        .context("Failed to deserialize lock file")?;
+
+    match value.get("Version").and_then(toml::Value::as_integer) {
+        Some(version) if version > LOCK_VERSION as i64 => bail!(
+            "Lock file version {} is not supported by this version of grill (max {})",
+            version,
+            LOCK_VERSION
+        ),
+        Some(version) => {
+            let mut lock: Lock = value
+                .try_into()
+                .context("Failed to deserialize lock file")?;
+            if version < 3 {
+                log::debug!("Dropping the checksums of a version {} lock file", version);
+                for package in lock.packages.iter_mut() {
+                    package.checksum = None;
+                }
+            }
+            Ok(lock)
+        }
+        None => {
+            log::debug!("Migrating lock file from version 1");
+            let versions: Resolution = value
+                .try_into()
+                .context("Failed to deserialize lock file")?;
+            Ok(Lock::from(versions))
+        }
+    }
 }
 
 pub fn write<P>(path: P, lock: &Lock) -> Result<()>
@@ -44,23 +196,57 @@ pub fn validate(pkg_path: &Path) -> Result<bool> {
     }
 
     let lock: Lock = self::read(file_path)?;
-    Ok(validate_lock(&manifest, &lock))
+    if lock.version != LOCK_VERSION {
+        log::debug!(
+            "Invalid lock: Lock file version {} is outdated",
+            lock.version
+        );
+        return Ok(false);
+    }
+
+    let sources = self::sources(pkg_path, &manifest, Some(&lock))?;
+    if let Some(missing) = sources.missing.first() {
+        log::debug!(
+            "Invalid lock: Git package {} is not on disk",
+            missing.package.name
+        );
+        return Ok(false);
+    }
+
+    let options = Options {
+        sources: sources.manifests,
+        patches: sources.patches,
+        ..Default::default()
+    };
+    let versions = lock.versions();
+    Ok(validate_lock(&manifest, &options, &versions)
+        && validate_sources(&manifest, &sources.packages, &options, &lock))
 }
 
-fn validate_lock(manifest: &Manifest, lock: &Lock) -> bool {
+fn validate_lock(manifest: &Manifest, options: &Options, versions: &Resolution) -> bool {
     log::trace!("Validating lock");
 
-    for (dep, req) in manifest.indexed_deps() {
-        if !lock
+    for (dep, req) in iter::once(manifest)
+        .chain(options.sources.values())
+        .flat_map(Manifest::indexed_deps)
+    {
+        let locked = match options
+            .sources
             .get(dep)
-            .is_some_and(|locked_versions| locked_versions.iter().any(|v| req.matches(v)))
+            .filter(|_| options.patches.contains(dep))
         {
+            Some(patch) => req.matches(&patch.package.version),
+            None => versions
+                .get(dep)
+                .is_some_and(|versions| versions.iter().any(|version| req.matches(version))),
+        };
+        if !locked {
             log::debug!("Invalid lock: No match for {} {}", dep, req);
             return false;
         }
     }
 
-    for (dep, versions) in lock {
+    for (dep, versions) in versions {
         for v1 in versions.iter() {
             for v2 in versions.iter() {
                 if v1.major == v2.major && v1 != v2 {
@@ -80,28 +266,509 @@ fn validate_lock(manifest: &Manifest, lock: &Lock) -> bool {
     true
 }
 
-pub fn generate(pkg_path: &Path, write_lock: bool, try_keep_lock: bool) -> Result<Lock> {
-    let manifest = Manifest::from_pkg(pkg_path).context("Failed to read manifest")?;
+/// Checks that the locked git and local packages are the ones in the manifests,
+/// and that they and the root package have the dependencies in their manifests.
+fn validate_sources(
+    manifest: &Manifest,
+    sources: &[LockedPackage],
+    options: &Options,
+    lock: &Lock,
+) -> bool {
+    let (roots, locked): (Vec<&LockedPackage>, Vec<&LockedPackage>) = lock
+        .packages
+        .iter()
+        .filter(|package| package.source != Source::Index)
+        .partition(|package| package.is_root());
 
-    let lock_path = pkg_path.join(crate::paths::LOCK_FILENAME);
-    let previous_lock = if try_keep_lock && lock_path.exists() {
-        let lock = self::read(lock_path)?;
-        if validate_lock(&manifest, &lock) {
-            Some(lock)
-        } else {
-            None
+    if !roots.iter().any(|root| {
+        root.dependencies
+            == manifest_dependencies(manifest, options, &locked_choices(root, manifest))
+    }) {
+        log::debug!("Invalid lock: Dependencies of the root package have changed");
+        return false;
+    }
+
+    for source in sources.iter() {
+        let manifest = &options.sources[&source.name];
+        if !locked.iter().any(|package| {
+            package.is_same(source)
+                && package.dependencies
+                    == manifest_dependencies(manifest, options, &locked_choices(package, manifest))
+        }) {
+            log::debug!(
+                "Invalid lock: {} v{} is not locked",
+                source.name,
+                source.version
+            );
+            return false;
         }
+    }
+
+    if locked.len() != sources.len() {
+        log::debug!("Invalid lock: Lock contains removed git or local packages");
+        return false;
+    }
+
+    true
+}
+
+/// The parts of the previous lock that are kept when generating a new one.
+#[derive(Debug, Clone, Copy)]
+pub enum Keep<'a> {
+    Nothing,
+    All,
+    /// Everything except these packages, which are updated.
+    AllExcept(&'a [String]),
+}
+
+impl Keep<'_> {
+    fn keeps(&self, name: &str) -> bool {
+        match self {
+            Keep::Nothing => false,
+            Keep::All => true,
+            Keep::AllExcept(names) => !names.iter().any(|n| n == name),
+        }
+    }
+}
+
+/// Resolves the dependencies of a package into a new lock.
+///
+/// Git dependencies that aren't on disk are passed to `fetch`, which
+/// returns the revision they were installed at.
+///
+/// In locked mode the lock file is never written, and an error is
+/// returned if the new lock is different from the one on disk.
+pub fn generate<F>(
+    pkg_path: &Path,
+    write_lock: bool,
+    try_keep_lock: bool,
+    locked: bool,
+    fetch: F,
+) -> Result<Lock>
+where
+    F: FnMut(&MissingSource) -> Result<String>,
+{
+    let keep = if try_keep_lock {
+        Keep::All
     } else {
+        Keep::Nothing
+    };
+    generate_with(
+        pkg_path,
+        write_lock,
+        locked,
+        keep,
+        Strategy::default(),
+        fetch,
+    )
+}
+
+pub fn generate_with<F>(
+    pkg_path: &Path,
+    write_lock: bool,
+    locked: bool,
+    keep: Keep,
+    strategy: Strategy,
+    mut fetch: F,
+) -> Result<Lock>
+where
+    F: FnMut(&MissingSource) -> Result<String>,
+{
+    let manifest = Manifest::from_pkg(pkg_path).context("Failed to read manifest")?;
+
+    let lock_path = pkg_path.join(crate::paths::LOCK_FILENAME);
+    let previous_lock = if !lock_path.exists() {
         None
+    } else if let Keep::Nothing = keep {
+        self::read(&lock_path).ok()
+    } else {
+        Some(self::read(&lock_path)?)
+    };
+    let mut kept_lock = Lock {
+        version: LOCK_VERSION,
+        packages: previous_lock
+            .iter()
+            .flat_map(|lock| lock.packages.iter())
+            .filter(|package| keep.keeps(&package.name))
+            .cloned()
+            .collect(),
+    };
+
+    // Fetching a git dependency can reveal more git dependencies
+    let mut fetched = HashSet::new();
+    let sources = loop {
+        let sources = self::sources(pkg_path, &manifest, Some(&kept_lock))?;
+        if sources.missing.is_empty() {
+            break sources;
+        }
+
+        for missing in sources.missing {
+            let name = &missing.package.name;
+            if !fetched.insert(name.clone()) {
+                bail!("Git package {} is still missing after fetching it", name);
+            }
+
+            let rev = fetch(&missing)?;
+            kept_lock
+                .packages
+                .retain(|package| !(package.source == Source::Git && &package.name == name));
+            kept_lock.packages.push(LockedPackage {
+                rev: Some(rev),
+                ..missing.package
+            });
+        }
+    };
+    let options = Options {
+        sources: sources.manifests,
+        patches: sources.patches,
+        strategy,
     };
 
-    let index = crate::index::parse(false, false)?;
+    let previous_versions = previous_lock
+        .as_ref()
+        .filter(|_| !matches!(keep, Keep::Nothing))
+        .map(Lock::versions)
+        .filter(|versions| validate_lock(&manifest, &options, versions))
+        .map(|mut versions| {
+            versions.retain(|name, _| keep.keeps(name));
+            versions
+        });
 
-    let lock = resolve(&manifest, previous_lock.as_ref(), &index)?;
+    let index = crate::index::load(
+        iter::once(&manifest)
+            .chain(options.sources.values())
+            .flat_map(Manifest::indexed_deps)
+            .map(|(name, _)| name),
+        false,
+        false,
+    )?;
+
+    let (resolution, choices) =
+        resolver::resolve_with(&manifest, previous_versions.as_ref(), &index, &options)?;
+
+    let lock = build(
+        &manifest,
+        sources.packages,
+        &options,
+        &index,
+        &resolution,
+        &choices,
+        previous_lock.as_ref(),
+        keep,
+    );
+
+    if locked {
+        match previous_lock {
+            None => bail!("Package.lock needs to be created, but --locked was passed"),
+            Some(previous_lock) if previous_lock != lock => {
+                bail!("Package.lock needs to be updated, but --locked was passed")
+            }
+            Some(_) => return Ok(lock),
+        }
+    }
 
     if write_lock {
-        self::write(pkg_path.join(crate::paths::LOCK_FILENAME), &lock)?;
+        self::write(lock_path, &lock)?;
     }
 
     Ok(lock)
 }
+
+/// Creates the lock entries of a resolution, with the dependencies of each package.
+/// Indexed packages that were already locked and are kept keep their
+/// revision, even if the index entry has changed since. Checksums are kept for
+/// packages that are still locked at the same revision.
+#[allow(clippy::too_many_arguments)]
+fn build(
+    manifest: &Manifest,
+    sources: Vec<LockedPackage>,
+    options: &Options,
+    index: &Index,
+    resolution: &Resolution,
+    choices: &Choices,
+    previous_lock: Option<&Lock>,
+    keep: Keep,
+) -> Lock {
+    let mut packages = vec![LockedPackage {
+        name: manifest.package.name.clone(),
+        version: manifest.package.version.clone(),
+        source: Source::Local,
+        url: None,
+        rev: None,
+        branch: None,
+        tag: None,
+        checksum: None,
+        path: Some(PathBuf::from(".")),
+        dependencies: manifest_dependencies(manifest, options, choices),
+    }];
+
+    packages.extend(sources.into_iter().map(|mut package| {
+        package.dependencies =
+            manifest_dependencies(&options.sources[&package.name], options, choices);
+        package
+    }));
+
+    for (name, versions) in resolution {
+        let entry = &index[name];
+        for version in versions {
+            let metadata = &entry.versions[version];
+            let previous = previous_lock.and_then(|lock| {
+                lock.packages_from(Source::Index)
+                    .find(|package| &package.name == name && &package.version == version)
+            });
+            let rev = previous
+                .and_then(|package| package.rev.clone())
+                .filter(|_| keep.keeps(name))
+                .unwrap_or_else(|| metadata.rev.clone());
+            let checksum = previous
+                .filter(|package| package.rev.as_ref() == Some(&rev))
+                .and_then(|package| package.checksum.clone());
+
+            packages.push(LockedPackage {
+                name: name.clone(),
+                version: version.clone(),
+                source: Source::Index,
+                url: Some(entry.url.clone()),
+                rev: Some(rev),
+                branch: None,
+                tag: None,
+                checksum,
+                path: None,
+                dependencies: metadata
+                    .deps
+                    .iter()
+                    .filter_map(|(dep, req)| locked_dependency(choices, options, dep, req))
+                    .sorted()
+                    .collect(),
+            });
+        }
+    }
+
+    packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+    Lock {
+        version: LOCK_VERSION,
+        packages,
+    }
+}
+
+fn manifest_dependencies(manifest: &Manifest, options: &Options, choices: &Choices) -> Vec<String> {
+    manifest
+        .indexed_deps()
+        .filter_map(|(dep, req)| locked_dependency(choices, options, dep, req))
+        .chain(
+            manifest
+                .local_deps()
+                .map(|(name, _)| name)
+                .chain(manifest.git_deps().map(|(name, _)| name))
+                .filter_map(|name| {
+                    let source = options.sources.get(name)?;
+                    Some(format!("{} {}", name, source.package.version))
+                }),
+        )
+        .sorted()
+        .collect()
+}
+
+/// The locked version a requirement is connected to, which is the version
+/// the resolver chose for it. Patched packages are connected to the patch,
+/// if its version matches.
+fn locked_dependency(
+    choices: &Choices,
+    options: &Options,
+    name: &str,
+    req: &VersionReq,
+) -> Option<String> {
+    if options.patches.contains(name) {
+        let version = &options.sources.get(name)?.package.version;
+        return req
+            .matches(version)
+            .then(|| format!("{} {}", name, version));
+    }
+
+    choices
+        .get(&(name.to_owned(), req.clone()))
+        .map(|version| format!("{} {}", name, version))
+}
+
+/// The versions the requirements of a locked package were resolved to,
+/// taken from its lock entry.
+fn locked_choices(package: &LockedPackage, manifest: &Manifest) -> Choices {
+    manifest
+        .indexed_deps()
+        .filter_map(|(name, req)| {
+            let version = package
+                .dependencies
+                .iter()
+                .filter_map(|dep| dep.split_once(' '))
+                .filter(|(dep, _)| dep == name)
+                .filter_map(|(_, version)| Version::parse(version).ok())
+                .find(|version| req.matches(version))?;
+            Some(((name.clone(), req.clone()), version))
+        })
+        .collect()
+}
+
+/// The git and local dependencies reachable from a package.
+#[derive(Debug, Default)]
+pub struct Sources {
+    /// Manifests by dependency name.
+    pub manifests: HashMap<String, Manifest>,
+    /// Lock entries, without their dependencies.
+    pub packages: Vec<LockedPackage>,
+    /// Names of the packages from the `[Patch]` section.
+    pub patches: HashSet<String>,
+    /// Git dependencies that aren't on disk yet. Their own
+    /// dependencies are unknown until they are fetched.
+    pub missing: Vec<MissingSource>,
+}
+
+/// A git dependency that isn't installed at the revision it needs.
+#[derive(Debug, Clone)]
+pub struct MissingSource {
+    /// The lock entry, with the revision to install if it is known.
+    pub package: LockedPackage,
+    /// The branch, tag or `HEAD` to resolve if there is no revision.
+    pub reference: Option<String>,
+}
+
+/// Finds all git and local dependencies reachable from the package,
+/// and the patches of the package. Only reads what is on disk: git
+/// dependencies that aren't installed are listed as missing. Git branches
+/// and tags are resolved to the commit in the lock, if it has one for them.
+pub fn sources(ws_path: &Path, manifest: &Manifest, lock: Option<&Lock>) -> Result<Sources> {
+    let mut sources = Sources::default();
+    let mut visited = HashSet::new();
+    visited.insert(ws_path.canonicalize()?);
+
+    let mut queue = source_paths(ws_path, ws_path, manifest, lock, &mut sources.missing)?;
+    for (name, patch) in manifest.patch.iter() {
+        sources.patches.insert(name.clone());
+        match patch {
+            Patch::Git(dep) => match git_source(ws_path, name, dep, lock)? {
+                Either::Left(source) => queue.push(source),
+                Either::Right(missing) => sources.missing.push(missing),
+            },
+            Patch::Local(local) => queue.push(local_source(ws_path, ws_path, name, &local.path)),
+        }
+    }
+    while let Some((path, mut package)) = queue.pop() {
+        if !visited.insert(path.canonicalize()?) {
+            continue;
+        }
+
+        let manifest = Manifest::from_pkg(&path)
+            .with_context(|| format!("Failed to read manifest of dependency '{}'", package.name))?;
+        queue.extend(source_paths(
+            ws_path,
+            &path,
+            &manifest,
+            lock,
+            &mut sources.missing,
+        )?);
+
+        package.version = manifest.package.version.clone();
+        sources.manifests.insert(package.name.clone(), manifest);
+        sources.packages.push(package);
+    }
+
+    Ok(sources)
+}
+
+/// Paths to the git and local dependencies of a package,
+/// with their lock entries. The versions are filled in later.
+fn source_paths(
+    ws_path: &Path,
+    pkg_path: &Path,
+    manifest: &Manifest,
+    lock: Option<&Lock>,
+    missing: &mut Vec<MissingSource>,
+) -> Result<Vec<(PathBuf, LockedPackage)>> {
+    let mut paths = Vec::new();
+
+    for (name, dep) in manifest.local_deps() {
+        paths.push(local_source(ws_path, pkg_path, name, &dep.path));
+    }
+
+    for (name, dep) in manifest.git_deps() {
+        match git_source(ws_path, name, dep, lock)? {
+            Either::Left(source) => paths.push(source),
+            Either::Right(source) => missing.push(source),
+        }
+    }
+
+    Ok(paths)
+}
+
+fn local_source(
+    ws_path: &Path,
+    pkg_path: &Path,
+    name: &str,
+    path: &Path,
+) -> (PathBuf, LockedPackage) {
+    let path = pkg_path.join(path);
+    (
+        path.clone(),
+        LockedPackage {
+            name: name.to_owned(),
+            version: Version::new(0, 0, 0),
+            source: Source::Local,
+            url: None,
+            rev: None,
+            branch: None,
+            tag: None,
+            checksum: None,
+            path: Some(path.strip_prefix(ws_path).unwrap_or(&path).to_path_buf()),
+            dependencies: Vec::new(),
+        },
+    )
+}
+
+/// The path and lock entry of a git dependency, if it is installed
+/// at the revision it needs.
+fn git_source(
+    ws_path: &Path,
+    name: &String,
+    dep: &GitDependency,
+    lock: Option<&Lock>,
+) -> Result<Either<(PathBuf, LockedPackage), MissingSource>> {
+    let reference = dep.reference()?;
+    let rev = match reference {
+        Some(_) => lock
+            .into_iter()
+            .flat_map(|lock| lock.packages_from(Source::Git))
+            .find(|package| {
+                &package.name == name
+                    && package.url.as_ref() == Some(&dep.git)
+                    && package.branch == dep.branch
+                    && package.tag == dep.tag
+            })
+            .and_then(|package| package.rev.clone()),
+        None => dep.rev.clone(),
+    };
+
+    let package = LockedPackage {
+        name: name.clone(),
+        version: Version::new(0, 0, 0),
+        source: Source::Git,
+        url: Some(dep.git.clone()),
+        rev,
+        branch: dep.branch.clone(),
+        tag: dep.tag.clone(),
+        checksum: None,
+        path: dep.path.clone(),
+        dependencies: Vec::new(),
+    };
+
+    let repo_path = crate::paths::pkg(ws_path, Path::new(name));
+    match &package.rev {
+        Some(rev) if crate::ops::is_installed(&repo_path, Some(rev)) => {
+            let path = match &dep.path {
+                Some(path) => repo_path.join(path),
+                None => repo_path,
+            };
+            Ok(Either::Left((path, package)))
+        }
+        _ => Ok(Either::Right(MissingSource { package, reference })),
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::{bail, Result};
 use grill::paths;
 
 fn main() -> Result<()> {
-    rm_rf::ensure_removed(paths::tmp())?;
+    paths::clean_tmp()?;
 
     let result = {
         let args = grill::cli().get_matches();
@@ -13,9 +13,13 @@ fn main() -> Result<()> {
             grill::log::init(log::LevelFilter::Info)?;
         }
 
+        grill::config::init(args.is_present("offline") || args.is_present("frozen"))?;
+
         match args.subcommand() {
             Some((cmd, args)) => match cmd {
                 "add" => grill::commands::add::exec(args),
+                "cache" => grill::commands::cache::exec(args),
+                "info" => grill::commands::info::exec(args),
                 "init" => grill::commands::init::exec(args),
                 "install" => grill::commands::install::exec(args),
                 "list" => grill::commands::list::exec(args),
@@ -25,7 +29,9 @@ fn main() -> Result<()> {
                 "publish" => grill::commands::publish::exec(args),
                 "purge" => grill::commands::purge::exec(args),
                 "rebuild" => grill::commands::rebuild::exec(args),
+                "search" => grill::commands::search::exec(args),
                 "update" => grill::commands::update::exec(args),
+                "yank" => grill::commands::yank::exec(args),
                 _ => bail!("Unkown command: {}", cmd),
             },
             None => {
@@ -35,6 +41,7 @@ fn main() -> Result<()> {
         }
     };
 
+    let failed = result.is_err();
     if let Err(err) = result {
         println!();
 
@@ -49,8 +56,9 @@ fn main() -> Result<()> {
         println!();
     }
 
-    if !cfg!(debug_assertions) {
-        rm_rf::ensure_removed(paths::tmp())?;
+    // Failed commands, like `--locked` with an outdated lock, have to fail CI
+    if failed {
+        std::process::exit(1);
     }
 
     Ok(())
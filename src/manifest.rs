@@ -6,6 +6,7 @@ use std::{
 use anyhow::Context;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
@@ -16,6 +17,10 @@ pub struct Manifest {
     pub dependencies: HashMap<String, Dependency>,
     #[serde(default)]
     pub features: Features,
+    /// Packages that replace the indexed package of the same name in the
+    /// whole dependency graph. Only the patches of the root package are used.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub patch: HashMap<String, Patch>,
 }
 
 impl Manifest {
@@ -91,6 +96,106 @@ pub struct Package {
     pub description: String,
     #[serde(default = "bool_true")]
     pub corlib: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    /// Checked to be a valid url by [`Package::validate`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// Path to the readme, relative to the package.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readme: Option<PathBuf>,
+}
+
+impl Package {
+    pub const MAX_KEYWORDS: usize = 5;
+    pub const MAX_CATEGORIES: usize = 5;
+    pub const MAX_KEYWORD_LEN: usize = 20;
+
+    /// Metadata that should be filled in before publishing, but isn't required.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.description.trim().is_empty() {
+            warnings.push(String::from("Description is empty"));
+        }
+
+        if self.license.as_ref().is_none_or(|l| l.trim().is_empty()) {
+            warnings.push(String::from("License is not specified"));
+        }
+
+        warnings
+    }
+
+    /// Check that the metadata is valid enough to be published.
+    /// Returns a list of problems, which is empty if the package is valid.
+    pub fn validate<P>(&self, pkg_path: P) -> Vec<String>
+    where
+        P: AsRef<Path>,
+    {
+        let mut problems = Vec::new();
+
+        if self.authors.iter().any(|a| a.trim().is_empty()) {
+            problems.push(String::from("Authors can't be empty"));
+        }
+
+        if self.keywords.len() > Self::MAX_KEYWORDS {
+            problems.push(format!(
+                "Too many keywords ({}, max {})",
+                self.keywords.len(),
+                Self::MAX_KEYWORDS
+            ));
+        }
+
+        for keyword in self.keywords.iter() {
+            if keyword.is_empty()
+                || keyword.len() > Self::MAX_KEYWORD_LEN
+                || !keyword
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                problems.push(format!(
+                    "Invalid keyword '{}': keywords must be 1-{} alphanumeric characters, '-' or '_'",
+                    keyword,
+                    Self::MAX_KEYWORD_LEN
+                ));
+            }
+        }
+
+        for (field, url) in [
+            ("Repository", &self.repository),
+            ("Homepage", &self.homepage),
+        ] {
+            if let Some(url) = url {
+                if let Err(err) = Url::parse(url) {
+                    problems.push(format!("{} '{}' is not a valid url: {}", field, url, err));
+                }
+            }
+        }
+
+        if self.categories.len() > Self::MAX_CATEGORIES {
+            problems.push(format!(
+                "Too many categories ({}, max {})",
+                self.categories.len(),
+                Self::MAX_CATEGORIES
+            ));
+        }
+
+        if let Some(readme) = &self.readme {
+            if !pkg_path.as_ref().join(readme).is_file() {
+                problems.push(format!("Readme '{}' does not exist", readme.display()));
+            }
+        }
+
+        problems
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -122,8 +227,33 @@ pub struct AdvancedDependency {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "PascalCase", deny_unknown_fields)]
 pub struct GitDependency {
-    pub git: url::Url,
-    pub rev: String,
+    pub git: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Directory of the package in the repository.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl GitDependency {
+    /// The git reference to check out, if it isn't a fixed revision.
+    /// Without `Rev`, `Branch` or `Tag`, the default branch is used.
+    pub fn reference(&self) -> anyhow::Result<Option<String>> {
+        match (&self.rev, &self.branch, &self.tag) {
+            (Some(_), None, None) => Ok(None),
+            (None, Some(branch), None) => Ok(Some(format!("refs/heads/{}", branch))),
+            (None, None, Some(tag)) => Ok(Some(format!("refs/tags/{}", tag))),
+            (None, None, None) => Ok(Some(String::from("HEAD"))),
+            _ => anyhow::bail!(
+                "Git dependency on {} can only have one of Rev, Branch and Tag",
+                self.git
+            ),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -136,6 +266,19 @@ pub struct LocalDependency {
     pub default_features: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Patch {
+    Git(GitDependency),
+    Local(LocalPatch),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct LocalPatch {
+    pub path: PathBuf,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "PascalCase")]
 pub struct Features {
@@ -155,3 +298,57 @@ pub enum Feature {
 fn bool_true() -> bool {
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+
+    fn manifest(package: &str) -> Manifest {
+        toml::from_str(&format!(
+            "[Package]\nName = \"pkg\"\nVersion = \"1.0.0\"\n{}",
+            package
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn validate() {
+        let valid = manifest(
+            r#"
+            Description = "A package"
+            License = "MIT"
+            Authors = ["Someone"]
+            Repository = "https://github.com/someone/pkg"
+            Keywords = ["beef", "game-dev"]
+            "#,
+        );
+        assert!(valid.package.validate(".").is_empty());
+        assert!(valid.package.warnings().is_empty());
+
+        // Missing metadata is only a warning
+        let incomplete = manifest("Description = \"\"");
+        assert!(incomplete.package.validate(".").is_empty());
+        assert_eq!(incomplete.package.warnings().len(), 2);
+
+        let invalid = manifest(
+            r#"
+            Description = " "
+            Authors = [""]
+            Repository = "github.com/someone/pkg"
+            Keywords = ["a", "b", "c", "d", "e", "no spaces"]
+            Readme = "DOES_NOT_EXIST.md"
+            "#,
+        );
+        let problems = invalid.package.validate(".");
+        assert_eq!(problems.len(), 5, "{:#?}", problems);
+        assert!(problems
+            .iter()
+            .any(|problem| problem.starts_with("Repository")));
+    }
+
+    #[test]
+    fn invalid_url_parses() {
+        let manifest = manifest("Description = \"\"\nHomepage = \"not a url\"");
+        assert_eq!(manifest.package.homepage.as_deref(), Some("not a url"));
+    }
+}
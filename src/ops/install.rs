@@ -1,27 +1,37 @@
 use std::{
     fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use anyhow::Context;
 use reqwest::Url;
 use semver::Version;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     beef,
+    cache::Cache,
+    config,
     index::{self, Index},
+    lock::{LockedPackage, MissingSource},
     manifest::Manifest,
     paths,
     prelude::*,
 };
 
 /// Returns the path to the installed package and a bool indicating
-/// if the package was downloaded.
+/// if the package was downloaded. Downloaded packages are only complete
+/// once their build script ran and [`mark_installed`] was called.
+///
+/// If the package is locked, the locked revision is used instead of the one
+/// in the index, and the checkout is verified against the locked checksum.
+/// Installed packages that don't match it are installed again.
 pub fn install<S, C>(
     ws: &Path,
     pkg: S,
     version: &Version,
     index: Option<&Index>,
+    locked: Option<&LockedPackage>,
     progress_callback: C,
 ) -> Result<(PathBuf, PathBuf, bool)>
 where
@@ -33,7 +43,7 @@ where
     let mut owned_index = None;
     let index = index.map_or_else(
         || -> Result<&Index> {
-            owned_index = Some(index::parse(false, false)?);
+            owned_index = Some(index::load([pkg], false, false)?);
             Ok(owned_index.as_ref().unwrap())
         },
         Ok,
@@ -49,81 +59,186 @@ where
         .get(version)
         .with_context(|| format!("{} is not a version of '{}'", version, pkg))?;
 
+    let rev = match locked.and_then(|locked| locked.rev.as_ref()) {
+        Some(rev) => {
+            // Branches and tags in the index are locked as the commit they pointed to
+            if rev != &metadata.rev && is_commit(&metadata.rev) {
+                log::warn!(
+                    "The index has changed the revision of {} v{} from {} to {}, using the locked revision",
+                    pkg,
+                    version,
+                    rev,
+                    metadata.rev
+                );
+            }
+            rev
+        }
+        None => &metadata.rev,
+    };
+    let checksum = locked.and_then(|locked| locked.checksum.as_deref());
+
     let ident = format!("{}-{}", pkg, version);
     let path = paths::pkg(ws, Path::new(&ident));
     if path.exists() {
-        return Ok((paths::pkg(".", &ident), path, false));
+        if !is_installed(&path, Some(rev)) {
+            log::warn!(
+                "{} v{} wasn't installed completely, installing it again",
+                pkg,
+                version
+            );
+        } else if let Err(err) = verify(&path, rev, checksum) {
+            log::warn!(
+                "{} v{} doesn't match the lock, installing it again: {:#}",
+                pkg,
+                version,
+                err
+            );
+        } else {
+            return Ok((paths::pkg(".", &ident), path, false));
+        }
+        rm_rf::ensure_removed(&path)?;
     }
 
     let (relative_path, full_path, _) = install_git(
         ws,
         &entry.url,
-        Some(&metadata.rev),
+        Some(rev),
         Some(&ident),
+        None,
         progress_callback,
     )?;
+    if let Err(err) = verify(&full_path, rev, checksum) {
+        rm_rf::ensure_removed(&full_path)?;
+        return Err(err.context(format!("Failed to verify {} v{}", pkg, version)));
+    }
 
     Ok((relative_path, full_path, true))
 }
 
+/// Checksum of the files of a package, as they are on disk. Only files
+/// tracked by git are included, except the ones grill rewrites.
+pub fn checksum(path: &Path) -> Result<String> {
+    let repo = git2::Repository::open(path)
+        .with_context(|| format!("Failed to open package at '{}'", path.display()))?;
+    let workdir = repo
+        .workdir()
+        .context("Package repository has no working directory")?;
+
+    let mut files = String::new();
+    for entry in repo.index()?.iter() {
+        let file = String::from_utf8_lossy(&entry.path);
+        let file_path = workdir.join(&*file);
+        if file_path
+            .file_name()
+            .is_some_and(|name| name == "BeefProj.toml" || name == paths::INSTALLED_FILENAME)
+        {
+            continue;
+        }
+
+        let id = git2::Oid::hash_file(git2::ObjectType::Blob, &file_path)
+            .with_context(|| format!("Failed to read '{}'", file_path.display()))?;
+        files.push_str(&format!("{} {}\n", id, file));
+    }
+
+    Ok(git2::Oid::hash_object(git2::ObjectType::Blob, files.as_bytes())?.to_string())
+}
+
+/// The id of the commit checked out at `path`.
+pub fn head_commit(path: &Path) -> Result<String> {
+    let repo = git2::Repository::open(path)
+        .with_context(|| format!("Failed to open package at '{}'", path.display()))?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    Ok(head.to_string())
+}
+
+/// Returns true if `rev` is a full commit id rather than a branch or tag.
+pub fn is_commit(rev: &str) -> bool {
+    rev.len() == 40 && git2::Oid::from_str(rev).is_ok()
+}
+
+/// Checks that the package is checked out at `rev`, and that its
+/// contents match the checksum, if any.
+fn verify(path: &Path, rev: &str, checksum: Option<&str>) -> Result<()> {
+    let repo = git2::Repository::open(path)
+        .with_context(|| format!("Failed to open package at '{}'", path.display()))?;
+    let head = repo.head()?.peel_to_commit()?;
+    let expected = repo
+        .revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| format!("Locked revision {} doesn't exist", rev))?;
+
+    if head.id() != expected.id() {
+        bail!(
+            "Checked out commit {} is not the locked revision {}",
+            head.id(),
+            rev
+        );
+    }
+
+    if let Some(checksum) = checksum {
+        let actual = self::checksum(path)?;
+        if actual != checksum {
+            bail!(
+                "Checksum mismatch: the lock expects {}, but the contents are {}",
+                checksum,
+                actual
+            );
+        }
+    }
+
+    Ok(())
+}
+
 /// Returns the path to the installed package, first relative to the workspace,
 /// then relative to the working directory. Last is the revision that was checked out.
+/// Packages with an identifier are complete once [`mark_installed`] was called.
+///
+/// If `subdir` is set, the package is in that directory of the repository.
+/// The whole repository is still checked out.
 pub fn install_git<C>(
     ws: &Path,
     url: &Url,
     rev: Option<&str>,
     pkg_ident: Option<&String>,
-    mut progress_callback: C,
+    subdir: Option<&Path>,
+    progress_callback: C,
 ) -> Result<(PathBuf, PathBuf, String)>
 where
     C: FnMut(git2::Progress<'_>),
 {
-    rm_rf::ensure_removed(paths::tmp())?;
-
-    // let repo = Repository::init(&paths::tmp())?;
-    // let mut remote = repo.remote("origin", url.as_str())?;
-    let checkout_rev;
-    {
-        let mut callbacks = git2::RemoteCallbacks::new();
-        callbacks.transfer_progress(|p| {
-            progress_callback(p);
-            true
-        });
-
-        // remote.download::<String>(
-        //     &[],
-        //     Some(git2::FetchOptions::new().remote_callbacks(callbacks)),
-        // )?;
-
-        // log::trace!(
-        //     "Downloaded from remote: {}",
-        //     remote.url().unwrap_or("Url unavailable")
-        // );
-        let mut fo = git2::FetchOptions::new();
-        fo.remote_callbacks(callbacks);
-        let repo = git2::build::RepoBuilder::new()
-            .fetch_options(fo)
-            .clone(url.as_str(), &paths::tmp())?;
-
-        let head = repo
-            .find_commit(repo.head().unwrap().target().unwrap())?
-            .id()
-            .to_string();
-
-        // drop(remote);
-
-        checkout_rev = rev.map(str::to_owned).unwrap_or(head);
-
-        let (object, reference) = repo.revparse_ext(&checkout_rev)?;
-        repo.checkout_tree(&object, None)?;
-        match reference {
-            Some(gref) => repo.set_head(gref.name().with_context(|| "Invalid gref name")?),
-            None => repo.set_head_detached(object.id()),
-        }?;
-
-        // Dropping the repository gives us access to the directory
+    if let Some(subdir) = subdir {
+        if !subdir
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!(
+                "Path '{}' in {} needs to be a relative path inside the repository",
+                subdir.display(),
+                url
+            );
+        }
+    }
+    let in_repo = |path: PathBuf| match subdir {
+        Some(subdir) => path.join(subdir),
+        None => path,
+    };
+
+    if let (Some(ident), Some(rev)) = (pkg_ident, rev) {
+        let relative_path = paths::pkg("", ident);
+        let full_path = ws.join(&relative_path);
+        if is_installed(&full_path, Some(rev))
+            && in_repo(full_path.clone())
+                .join(paths::MANIFEST_FILENAME)
+                .exists()
+        {
+            return Ok((in_repo(relative_path), in_repo(full_path), rev.to_owned()));
+        }
     }
 
+    let cache = Cache::global();
+    let (_, commit) = cache.fetch(url, rev, progress_callback)?;
+    let checkout_rev = rev.map_or_else(|| commit.clone(), str::to_owned);
+
     let relative_path = pkg_ident
         .map(|ident| paths::pkg("", ident))
         .unwrap_or_else(|| {
@@ -136,18 +251,134 @@ where
     let full_path = ws.join(&relative_path);
 
     if full_path.exists() {
-        return Ok((relative_path, full_path, checkout_rev));
+        if pkg_ident.is_none() {
+            return Ok((relative_path, full_path, checkout_rev));
+        }
+
+        // The package is checked out at another revision
+        rm_rf::ensure_removed(&full_path)?;
     }
 
-    fs::rename(paths::tmp(), &full_path).context("Failed to move tmp folder")?;
+    cache.populate(&commit, &full_path, config::get().cache_mode())?;
 
+    let (relative_path, full_path) = (in_repo(relative_path), in_repo(full_path));
     if pkg_ident.is_some() {
+        if !full_path.join(paths::MANIFEST_FILENAME).exists() {
+            bail!(
+                "No package found at '{}' in {}",
+                subdir.unwrap_or_else(|| Path::new(".")).display(),
+                url
+            );
+        }
         prepare_pkg(&full_path, pkg_ident.map(String::as_str))?;
     }
 
     Ok((relative_path, full_path, checkout_rev))
 }
 
+/// Written into the repository of a package once it was checked out,
+/// prepared and built, so that a failed install isn't mistaken for a
+/// complete one.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Installed {
+    rev: String,
+    checksum: String,
+}
+
+/// Marks the package checked out at `path` as completely installed.
+pub fn mark_installed(path: &Path) -> Result<()> {
+    let installed = Installed {
+        rev: head_commit(path)?,
+        checksum: checksum(path)?,
+    };
+    fs::write(
+        path.join(paths::INSTALLED_FILENAME),
+        toml::to_string(&installed)?,
+    )
+    .with_context(|| format!("Failed to mark {} as installed", path.display()))
+}
+
+/// Returns true if the package at `path` was completely installed and
+/// its files haven't changed since, checked out at `rev` if it is set.
+/// See [`checksum`] for the files that are compared.
+pub fn is_installed(path: &Path, rev: Option<&str>) -> bool {
+    let Some(installed) = fs::read_to_string(path.join(paths::INSTALLED_FILENAME))
+        .ok()
+        .and_then(|installed| toml::from_str::<Installed>(&installed).ok())
+    else {
+        return false;
+    };
+
+    is_checked_out(path, &installed.rev)
+        && rev.is_none_or(|rev| is_checked_out(path, rev))
+        && checksum(path).is_ok_and(|checksum| checksum == installed.checksum)
+}
+
+fn is_checked_out(path: &Path, rev: &str) -> bool {
+    let Ok(repo) = git2::Repository::open(path) else {
+        return false;
+    };
+    let head = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .map(|commit| commit.id());
+    let expected = repo
+        .revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .map(|commit| commit.id());
+    matches!((head, expected), (Ok(head), Ok(expected)) if head == expected)
+}
+
+/// Installs a git dependency that [`crate::lock::sources`] couldn't find on disk,
+/// and returns the revision it was installed at.
+pub fn fetch_source(ws: &Path, missing: &MissingSource) -> Result<String> {
+    let package = &missing.package;
+    let url = package
+        .url
+        .as_ref()
+        .with_context(|| format!("Git package {} has no url", package.name))?;
+    let rev = match (&package.rev, &missing.reference) {
+        (Some(rev), _) => rev.clone(),
+        (None, Some(reference)) => resolve_reference(url, reference)?,
+        (None, None) => bail!("Git package {} has no revision", package.name),
+    };
+
+    let (_, _, rev) = install_git(
+        ws,
+        url,
+        Some(&rev),
+        Some(&package.name),
+        package.path.as_deref(),
+        |_| {},
+    )?;
+    mark_installed(&paths::pkg(ws, Path::new(&package.name)))?;
+    Ok(rev)
+}
+
+/// Finds the commit a reference like `refs/heads/main` points to in a remote repository.
+pub fn resolve_reference(url: &Url, reference: &str) -> Result<String> {
+    if config::get().offline() {
+        bail!("Cannot look up {} of {} in offline mode", reference, url);
+    }
+
+    let mut remote = git2::Remote::create_detached(url.as_str())?;
+    remote
+        .connect(git2::Direction::Fetch)
+        .with_context(|| format!("Failed to connect to {}", url))?;
+
+    // Annotated tags are listed a second time, peeled to the commit
+    let peeled = format!("{}^{{}}", reference);
+    let heads = remote.list()?;
+    let head = heads
+        .iter()
+        .find(|head| head.name() == peeled)
+        .or_else(|| heads.iter().find(|head| head.name() == reference))
+        .with_context(|| format!("Could not find {} in {}", reference, url))?;
+
+    Ok(head.oid().to_string())
+}
+
 pub fn prepare_pkg(path: &Path, ident: Option<&str>) -> Result<()> {
     let ident = ident.map(str::to_owned).unwrap_or(
         path.file_name()
@@ -185,7 +416,7 @@ pub fn prepare_pkg(path: &Path, ident: Option<&str>) -> Result<()> {
         feature_proj_file.save()?;
     }
 
-    for feature_project in manifest.features.optional.iter().filter_map(|(_, f)| {
+    for feature_project in manifest.features.optional.values().filter_map(|f| {
         if let crate::manifest::Feature::Project(project) = f {
             Some(project)
         } else {
@@ -222,3 +453,55 @@ pub fn prepare_pkg(path: &Path, ident: Option<&str>) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    #[test]
+    fn checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        fs::write(dir.path().join("Package.toml"), "").unwrap();
+        fs::write(dir.path().join("BeefProj.toml"), "").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("Package.toml")).unwrap();
+        index.add_path(Path::new("BeefProj.toml")).unwrap();
+        index.write().unwrap();
+
+        let checksum = super::checksum(dir.path()).unwrap();
+
+        // Files grill rewrites and untracked files are ignored
+        fs::write(dir.path().join("BeefProj.toml"), "[Project]").unwrap();
+        fs::write(dir.path().join(".grill-installed"), "").unwrap();
+        fs::write(dir.path().join("build.log"), "").unwrap();
+        assert_eq!(super::checksum(dir.path()).unwrap(), checksum);
+
+        fs::write(dir.path().join("Package.toml"), "[Package]").unwrap();
+        assert_ne!(super::checksum(dir.path()).unwrap(), checksum);
+    }
+
+    #[test]
+    fn installed_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        fs::write(dir.path().join("Package.toml"), "").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("Package.toml")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("grill", "grill@localhost").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "Initial", &tree, &[])
+            .unwrap()
+            .to_string();
+        let checksum = super::checksum(dir.path()).unwrap();
+
+        // Checkouts without a marker may be incomplete, even if they match the lock
+        assert!(super::verify(dir.path(), &commit, Some(&checksum)).is_ok());
+        assert!(!super::is_installed(dir.path(), Some(&commit)));
+
+        super::mark_installed(dir.path()).unwrap();
+        assert!(super::is_installed(dir.path(), Some(&commit)));
+    }
+}
@@ -1,22 +1,34 @@
 use std::{
     collections::{HashMap, HashSet},
-    fs,
+    fs, iter,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
     time::Duration,
 };
 
 use console::Emoji;
 use either::{self, Either};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use semver::Version;
 
 use crate::{
-    beef, index, lock,
+    beef,
+    cache::Cache,
+    config, index,
+    lock::{self, Lock, LockedPackage, MissingSource, Source},
     manifest::{self, Manifest},
+    paths,
     prelude::*,
 };
 
-type Packages = HashMap<(String, Either<Version, String>), (PathBuf, PathBuf)>;
+type PackageKey = (String, Either<Version, String>);
+type Packages = HashMap<PackageKey, (PathBuf, PathBuf)>;
+/// Patched packages by name, with the version used in their identifier.
+type Patches = HashMap<String, (Option<Either<Version, String>>, (PathBuf, PathBuf))>;
 
 const COMPASS: Emoji = Emoji("🧭 ", "");
 const LOOKING_GLASS: Emoji = Emoji("🔍 ", "");
@@ -24,11 +36,24 @@ const TRUCK: Emoji = Emoji("🚚 ", "");
 const PACKAGE: Emoji = Emoji("📦 ", "");
 const SPAGHETTI: Emoji = Emoji("🍝 ", "");
 
-pub fn make<P>(ws_path: P, quiet: bool) -> Result<()>
+#[derive(Debug, Default)]
+pub struct MakeOptions {
+    pub quiet: bool,
+    /// Update the index even if it is still fresh.
+    pub refresh_index: bool,
+    /// How many packages are fetched at the same time.
+    /// Defaults to the number of CPUs.
+    pub jobs: Option<usize>,
+    /// Fail instead of writing changes to the lock file.
+    pub locked: bool,
+}
+
+pub fn make<P>(ws_path: P, options: &MakeOptions) -> Result<()>
 where
     P: AsRef<Path>,
 {
     let ws_path = ws_path.as_ref();
+    let quiet = options.quiet;
     let manifest = Manifest::from_pkg(ws_path)?;
 
     if !quiet {
@@ -42,6 +67,9 @@ where
 
     let multi = crate::log::get_multi_progress();
 
+    let previous_lock = lock::read(ws_path.join(paths::LOCK_FILENAME)).ok();
+    let sources = lock::sources(ws_path, &manifest, previous_lock.as_ref())?;
+
     // Invisible progress bar to create empty line between logs and progress bars
     if !quiet {
         let p =
@@ -58,12 +86,27 @@ where
         &COMPASS,
         quiet,
         |_, _| {
-            index::update(false, false)?;
-            index::parse(false, false)
+            // Skip the update if the index is recent enough or if the
+            // lock already satisfies the manifest.
+            if options.refresh_index
+                || (!config::get().offline() && !index::is_fresh() && !lock::validate(ws_path)?)
+            {
+                index::update(false, false)?;
+            } else {
+                log::debug!("Using cached index");
+            }
+            index::load(
+                iter::once(&manifest)
+                    .chain(sources.manifests.values())
+                    .flat_map(Manifest::indexed_deps)
+                    .map(|(name, _)| name),
+                false,
+                false,
+            )
         },
     )?;
 
-    let lock = make_step(
+    let mut lock = make_step(
         multi,
         2,
         4,
@@ -72,11 +115,15 @@ where
         &LOOKING_GLASS,
         quiet,
         |_, _| {
-            if !lock::validate(ws_path)? {
-                lock::generate(ws_path, true, true)
-            } else {
-                lock::read(ws_path.join(crate::paths::LOCK_FILENAME))
+            if lock::validate(ws_path)? {
+                return lock::read(ws_path.join(crate::paths::LOCK_FILENAME));
             }
+
+            // Report everything that is missing before git dependencies are fetched
+            check_offline(ws_path, previous_lock.as_ref(), &sources.missing)?;
+            lock::generate(ws_path, true, true, options.locked, |missing| {
+                crate::ops::fetch_source(ws_path, missing)
+            })
         },
     )?;
 
@@ -89,131 +136,107 @@ where
         &TRUCK,
         quiet,
         |multi, _| {
+            check_offline(ws_path, Some(&lock), &[])?;
+
+            let packages: Vec<&LockedPackage> = lock
+                .packages
+                .iter()
+                .filter(|package| package.source != Source::Local)
+                .collect();
+
             let progress = multi.add(
-                ProgressBar::new(1).with_style(
+                ProgressBar::new(packages.len() as u64).with_style(
                     ProgressStyle::default_bar()
                         .template("{prefix:>12} [{bar:40}] {msg:.bright.grey}")?
                         .progress_chars("=> "),
                 ),
             );
-            if !quiet {
-                progress
-                    .set_length(lock.values().map(|versions| versions.len()).sum::<usize>() as u64);
-                progress.set_prefix(format!(
-                    "{} / {}",
-                    0,
-                    progress
-                        .length()
-                        .ok_or_else(|| anyhow!("No progress length"))?
-                ));
+            if quiet {
+                progress.set_draw_target(ProgressDrawTarget::hidden());
             }
-
-            let mut pkgs = HashMap::new();
-            for (pkg, versions) in lock {
-                for version in versions {
-                    progress.set_message(format!("{} 0%", pkg));
-                    let (relative_path, full_path, fetched) = crate::ops::install(
-                        ws_path,
-                        &pkg,
-                        &version,
-                        Some(&index),
-                        |install_progress| {
-                            progress.set_message(format!(
-                                "{} {}%",
-                                pkg,
-                                (install_progress.indexed_objects() as f32
-                                    / install_progress.total_objects() as f32
-                                    * 100f32)
-                                    .floor(),
+            progress.set_prefix(format!("0 / {}", packages.len()));
+
+            let jobs = options
+                .jobs
+                .or_else(|| thread::available_parallelism().ok().map(usize::from))
+                .unwrap_or(1)
+                .max(1);
+            log::debug!("Fetching {} packages with {} jobs", packages.len(), jobs);
+
+            let queue = Mutex::new(packages.iter());
+            let results = Mutex::new(Vec::new());
+            let failed = AtomicBool::new(false);
+            thread::scope(|scope| {
+                for _ in 0..jobs.min(packages.len()) {
+                    scope.spawn(|| {
+                        while !failed.load(Ordering::Relaxed) {
+                            let Some(package) = queue.lock().unwrap().next().copied() else {
+                                break;
+                            };
+
+                            let result = fetch_package(ws_path, &index, package, multi, quiet);
+                            failed.fetch_or(result.is_err(), Ordering::Relaxed);
+                            progress.inc(1);
+                            progress.set_prefix(format!(
+                                "{} / {}",
+                                progress.position(),
+                                packages.len()
                             ));
-                            std::thread::sleep(std::time::Duration::from_millis(10));
-                        },
-                    )?;
-
-                    if fetched {
-                        progress.set_message(format!("{} (Build)", pkg));
-                        let spinner = multi.add(
-                            ProgressBar::new_spinner()
-                                .with_message(pkg.clone())
-                                .with_style(
-                                    ProgressStyle::default_spinner()
-                                        .template("{prefix:>12} {msg} {spinner}")?
-                                        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈✔"),
-                                ),
-                        );
-                        spinner.enable_steady_tick(std::time::Duration::from_millis(100));
-
-                        crate::ops::rebuild(&full_path, Some(&spinner))?;
-                        spinner.finish_and_clear();
-                        multi.remove(&spinner);
-                    }
-
-                    if !quiet {
-                        if fetched {
-                            multi.suspend(|| {
-                                println!(
-                                    "{:>12} {} v{}",
-                                    console::style("Fetched").bright().cyan(),
-                                    pkg,
-                                    version
-                                );
-                            });
+                            results.lock().unwrap().push(result);
                         }
-
-                        progress.inc(1);
-                        progress.set_prefix(format!(
-                            "{} / {}",
-                            progress.position(),
-                            progress
-                                .length()
-                                .ok_or_else(|| anyhow!("No progress length"))?
-                        ));
-                    }
-
-                    pkgs.insert(
-                        (pkg.clone(), either::Left(version.clone())),
-                        (relative_path, full_path),
-                    );
+                    });
                 }
+            });
+            progress.finish_and_clear();
+            let fetched = results
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .collect::<Result<Vec<_>>>()?;
+
+            // Build scripts run after the build scripts of their dependencies
+            let by_dependency: HashMap<String, &FetchedPackage> = fetched
+                .iter()
+                .map(|package| (package.dependency(), package))
+                .collect();
+            let mut built = HashSet::new();
+            for package in fetched.iter() {
+                build_package(package, &by_dependency, &mut built, options.locked, multi)?;
             }
 
-            for (name, dep) in manifest.git_deps() {
-                progress.set_message(format!("{} 0%", name));
-                let (relative_path, full_path, rev) = crate::ops::install_git(
-                    ws_path,
-                    &dep.git,
-                    Some(&dep.rev),
-                    Some(name),
-                    |install_progress| {
-                        progress.set_message(format!(
-                            "{} {}%",
-                            name,
-                            (install_progress.indexed_objects() as f32
-                                / install_progress.total_objects() as f32
-                                * 100f32)
-                                .round(),
-                        ))
-                    },
-                )?;
-
-                pkgs.insert(
-                    (name.clone(), either::Right(rev)),
-                    (relative_path, full_path),
-                );
-
-                if !quiet {
-                    progress.inc(1);
-                    progress.set_prefix(format!(
-                        "{} / {}",
-                        progress.position(),
-                        progress
-                            .length()
-                            .ok_or_else(|| anyhow!("No progress length"))?
+            let mut pkgs = HashMap::new();
+            let mut unlocked = Vec::new();
+            for fetched in fetched {
+                let package = fetched.package;
+                if let Some((commit, checksum)) = fetched.unlocked {
+                    unlocked.push((
+                        package.name.clone(),
+                        package.version.clone(),
+                        commit,
+                        checksum,
                     ));
                 }
+
+                pkgs.insert(fetched.key, fetched.paths);
+            }
+
+            // Record the commits and checksums of packages that were fetched for the first time
+            if options.locked {
+                log::debug!("Not recording {} checksums in locked mode", unlocked.len());
+            } else if !unlocked.is_empty() {
+                for (name, version, commit, checksum) in unlocked {
+                    if let Some(package) = lock.packages.iter_mut().find(|package| {
+                        package.source == Source::Index
+                            && package.name == name
+                            && package.version == version
+                    }) {
+                        package.rev = Some(commit);
+                        package.checksum = checksum;
+                    }
+                }
+                lock::write(ws_path.join(paths::LOCK_FILENAME), &lock)?;
             }
 
-            progress.finish_and_clear();
             Ok(pkgs)
         },
     )?;
@@ -260,6 +283,8 @@ where
             ws.locked.insert(String::from("corlib"));
 
             let mut connects = HashMap::new();
+            let locked = locked_packages(ws_path, &lock, &pkgs);
+            let patches = patches(ws_path, &manifest, &pkgs);
 
             connect(
                 &manifest.package.name,
@@ -271,6 +296,8 @@ where
                     ws: &mut ws,
                     ws_package_folder: &mut ws_package_folder,
                     connects: &mut connects,
+                    locked: &locked,
+                    patches: &patches,
                 },
             )?;
 
@@ -291,6 +318,236 @@ where
     Ok(())
 }
 
+/// A package that was put into the workspace in the fetch step.
+struct FetchedPackage<'a> {
+    package: &'a LockedPackage,
+    key: PackageKey,
+    paths: (PathBuf, PathBuf),
+    /// If the package was downloaded and its build script still has to run.
+    fetched: bool,
+    /// The commit and checksum of indexed packages, if the lock doesn't
+    /// have them yet.
+    unlocked: Option<(String, Option<String>)>,
+}
+
+impl FetchedPackage<'_> {
+    /// The package as it is listed in the dependencies of the lock.
+    fn dependency(&self) -> String {
+        format!("{} {}", self.package.name, self.package.version)
+    }
+}
+
+fn fetch_package<'a>(
+    ws_path: &Path,
+    index: &index::Index,
+    package: &'a LockedPackage,
+    multi: &MultiProgress,
+    quiet: bool,
+) -> Result<FetchedPackage<'a>> {
+    let name = &package.name;
+    let progress = if quiet {
+        ProgressBar::hidden()
+    } else {
+        multi.add(
+            ProgressBar::new(1).with_prefix(name.clone()).with_style(
+                ProgressStyle::default_bar()
+                    .template("{prefix:>12} [{bar:40}] {msg:.bright.grey}")?
+                    .progress_chars("=> "),
+            ),
+        )
+    };
+    let update_progress = |install_progress: git2::Progress<'_>| {
+        progress.set_length(install_progress.total_objects() as u64);
+        progress.set_position(install_progress.indexed_objects() as u64);
+        progress.set_message(format!(
+            "{} / {} objects",
+            install_progress.indexed_objects(),
+            install_progress.total_objects()
+        ));
+    };
+
+    let fetched = match package.source {
+        Source::Index => {
+            let version = &package.version;
+            let (relative_path, full_path, fetched) = crate::ops::install(
+                ws_path,
+                name,
+                version,
+                Some(index),
+                Some(package),
+                update_progress,
+            )?;
+
+            // Only fresh checkouts are checksummed, before the build script
+            // can change the files. Installed packages were verified against the lock.
+            let commit = crate::ops::head_commit(&full_path)?;
+            let checksum = match &package.checksum {
+                Some(checksum) => Some(checksum.clone()),
+                None if fetched => Some(crate::ops::checksum(&full_path)?),
+                None => None,
+            };
+            let unlocked = (package.rev.as_ref() != Some(&commit) || checksum != package.checksum)
+                .then_some((commit, checksum));
+
+            if fetched && !quiet {
+                multi.suspend(|| {
+                    println!(
+                        "{:>12} {} v{}",
+                        console::style("Fetched").bright().cyan(),
+                        name,
+                        version
+                    );
+                });
+            }
+
+            FetchedPackage {
+                package,
+                key: (name.clone(), either::Left(version.clone())),
+                paths: (relative_path, full_path),
+                fetched,
+                unlocked,
+            }
+        }
+        _ => {
+            let url = package
+                .url
+                .as_ref()
+                .with_context(|| format!("Locked git package {} has no url", name))?;
+            let (relative_path, full_path, rev) = crate::ops::install_git(
+                ws_path,
+                url,
+                package.rev.as_deref(),
+                Some(name),
+                package.path.as_deref(),
+                update_progress,
+            )?;
+            // Git packages have no build step
+            crate::ops::mark_installed(&paths::pkg(ws_path, Path::new(name)))?;
+
+            FetchedPackage {
+                package,
+                key: (name.clone(), either::Right(rev)),
+                paths: (relative_path, full_path),
+                fetched: false,
+                unlocked: None,
+            }
+        }
+    };
+
+    progress.finish_and_clear();
+    multi.remove(&progress);
+    Ok(fetched)
+}
+
+/// Runs the build script of a fetched package, after those of its dependencies.
+fn build_package<'a>(
+    package: &'a FetchedPackage,
+    by_dependency: &HashMap<String, &'a FetchedPackage>,
+    built: &mut HashSet<String>,
+    locked: bool,
+    multi: &MultiProgress,
+) -> Result<()> {
+    if !built.insert(package.dependency()) {
+        return Ok(());
+    }
+
+    for dependency in package.package.dependencies.iter() {
+        if let Some(dependency) = by_dependency.get(dependency) {
+            build_package(dependency, by_dependency, built, locked, multi)?;
+        }
+    }
+
+    if package.fetched {
+        let spinner = multi.add(
+            ProgressBar::new_spinner()
+                .with_message(package.package.name.clone())
+                .with_style(
+                    ProgressStyle::default_spinner()
+                        .template("{prefix:>12} {msg} {spinner}")?
+                        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈✔"),
+                ),
+        );
+        spinner.enable_steady_tick(Duration::from_millis(100));
+
+        crate::ops::rebuild(&package.paths.1, locked, Some(&spinner))?;
+        spinner.finish_and_clear();
+        multi.remove(&spinner);
+
+        crate::ops::mark_installed(&package.paths.1)?;
+    }
+
+    Ok(())
+}
+
+/// In offline mode, fails with a list of the packages that need the network.
+fn check_offline(ws_path: &Path, lock: Option<&Lock>, sources: &[MissingSource]) -> Result<()> {
+    if !config::get().offline() {
+        return Ok(());
+    }
+
+    let missing = missing_packages(ws_path, lock, sources);
+    if !missing.is_empty() {
+        bail!(
+            "Cannot fetch packages in offline mode. These packages need the network:\n    {}",
+            missing.join("\n    ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Packages in the lock, and git dependencies that weren't found on disk,
+/// that are neither installed nor in the cache.
+fn missing_packages(ws_path: &Path, lock: Option<&Lock>, sources: &[MissingSource]) -> Vec<String> {
+    let cache = Cache::global();
+    let cached = |package: &LockedPackage| {
+        package
+            .rev
+            .as_deref()
+            .is_some_and(|rev| cache.contains(rev))
+    };
+    let installed = |path: &Path, package: &LockedPackage| {
+        crate::ops::is_installed(path, package.rev.as_deref())
+    };
+    let git = |package: &LockedPackage| match &package.url {
+        Some(url) => format!("{} ({})", package.name, url),
+        None => package.name.clone(),
+    };
+    let packages = |source| {
+        lock.into_iter()
+            .flat_map(move |lock| lock.packages_from(source))
+    };
+
+    let mut missing: Vec<String> = packages(Source::Index)
+        .filter(|package| {
+            let path = paths::pkg(
+                ws_path,
+                Path::new(&format!("{}-{}", package.name, package.version)),
+            );
+            !installed(&path, package) && !cached(package)
+        })
+        .map(|package| format!("{} v{}", package.name, package.version))
+        .chain(
+            packages(Source::Git)
+                .filter(|package| {
+                    let path = paths::pkg(ws_path, Path::new(&package.name));
+                    !installed(&path, package) && !cached(package)
+                })
+                .map(git),
+        )
+        .chain(
+            sources
+                .iter()
+                .map(|source| &source.package)
+                .filter(|package| !cached(package))
+                .map(git),
+        )
+        .collect();
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
 #[allow(clippy::too_many_arguments)]
 fn make_step<F, T>(
     multi: &MultiProgress,
@@ -347,9 +604,6 @@ fn connect(
     is_pkg: bool,
     shared: &mut SharedConnectData,
 ) -> Result<String> {
-    if shared.connects.len() > 10 {
-        panic!();
-    }
     let full_pkg_path = pkg_path.1.canonicalize()?;
     if let Some(ident) = shared.connects.get(&full_pkg_path) {
         return Ok(ident.clone());
@@ -384,6 +638,9 @@ fn connect(
 
     proj.project.processor_macros.clear();
 
+    let (pkgs, patches) = (shared.pkgs, shared.patches);
+    let locked = shared.locked.get(&full_pkg_path).copied();
+
     'dep_loop: for (name, dep) in manifest.dependencies.iter() {
         log::debug!("Dependency: {}", name);
         if let manifest::Dependency::Local(local) = dep {
@@ -492,80 +749,66 @@ fn connect(
             continue;
         }
 
-        for ((pkg, version), (relative_path, full_path)) in shared.pkgs.iter() {
-            if pkg == name {
-                let mut features = None;
-                let mut default_features = true;
-                let add = match version {
-                    either::Left(version) => {
-                        if let manifest::Dependency::Simple(req) = dep {
-                            req.matches(version)
-                        } else if let manifest::Dependency::Advanced(dep) = dep {
-                            features = Some(&dep.features);
-                            default_features = dep.default_features;
-                            dep.req.matches(version)
-                        } else {
-                            false
-                        }
-                    }
-                    either::Right(rev) => {
-                        if let manifest::Dependency::Git(dep) = dep {
-                            &dep.rev == rev
-                        } else {
-                            false
-                        }
-                    }
-                };
+        let patch = match dep {
+            manifest::Dependency::Simple(_) | manifest::Dependency::Advanced(_) => {
+                patches.get(name)
+            }
+            _ => None,
+        };
+        let (pkg, version, (relative_path, full_path)) = match patch {
+            Some((version, paths)) => (name, version.as_ref(), paths),
+            None => match locked_dependency(pkgs, locked, name, dep) {
+                Some(((pkg, version), paths)) => (pkg, Some(version), paths),
+                None => {
+                    log::error!("{} missing dependency {}", pkg_name, name);
+                    continue;
+                }
+            },
+        };
 
-                if add {
-                    let mut dep_proj = beef::BeefProj::from_file(&full_path.join("BeefProj.toml"))?;
+        let mut dep_proj = beef::BeefProj::from_file(&full_path.join("BeefProj.toml"))?;
 
-                    let ident =
-                        connect(pkg, Some(version), (relative_path, full_path), true, shared)?;
+        let ident = connect(pkg, version, (relative_path, full_path), true, shared)?;
 
-                    proj.dependencies.insert(ident.clone(), String::from("*"));
+        proj.dependencies.insert(ident.clone(), String::from("*"));
 
-                    let dep_manifest = Manifest::from_pkg(full_path)?;
-                    if dep_manifest.features.optional.values().any(|f| {
-                        if let manifest::Feature::Project(p) = f {
-                            full_path.join(p).canonicalize().unwrap() == full_pkg_path
-                        } else {
-                            false
-                        }
-                    }) {
-                        continue 'dep_loop;
-                    }
+        let dep_manifest = Manifest::from_pkg(full_path)?;
+        if dep_manifest.features.optional.values().any(|f| {
+            if let manifest::Feature::Project(p) = f {
+                full_path.join(p).canonicalize().unwrap() == full_pkg_path
+            } else {
+                false
+            }
+        }) {
+            continue;
+        }
 
-                    if let Some(features) = features {
-                        let features: Box<dyn Iterator<Item = &String>> = if default_features {
-                            Box::new(features.iter().chain(dep_manifest.features.default.iter()))
-                        } else {
-                            Box::new(features.iter())
-                        };
-
-                        for feature in features {
-                            log::debug!("Enabling feature {} of {}", feature, name);
-                            let feature_idents =
-                                enable_feature((relative_path, full_path), feature, shared)?;
-
-                            proj.dependencies
-                                .extend(feature_idents.into_iter().map(|i| (i, String::from("*"))));
-
-                            dep_proj
-                                .project
-                                .processor_macros
-                                .insert(format!("FEATURE_{}", feature.to_uppercase()));
-                        }
+        if let manifest::Dependency::Advanced(dep) = dep {
+            let features: Box<dyn Iterator<Item = &String>> = if dep.default_features {
+                Box::new(
+                    dep.features
+                        .iter()
+                        .chain(dep_manifest.features.default.iter()),
+                )
+            } else {
+                Box::new(dep.features.iter())
+            };
 
-                        dep_proj.save()?;
-                    }
+            for feature in features {
+                log::debug!("Enabling feature {} of {}", feature, name);
+                let feature_idents = enable_feature((relative_path, full_path), feature, shared)?;
 
-                    continue 'dep_loop;
-                }
+                proj.dependencies
+                    .extend(feature_idents.into_iter().map(|i| (i, String::from("*"))));
+
+                dep_proj
+                    .project
+                    .processor_macros
+                    .insert(format!("FEATURE_{}", feature.to_uppercase()));
             }
-        }
 
-        log::error!("{} missing dependency {}", pkg_name, name);
+            dep_proj.save()?;
+        }
     }
 
     if is_pkg {
@@ -593,6 +836,93 @@ fn connect(
     Ok(pkg_ident)
 }
 
+/// The lock entries of the packages on disk, by their canonical path.
+fn locked_packages<'a>(
+    ws_path: &Path,
+    lock: &'a Lock,
+    pkgs: &Packages,
+) -> HashMap<PathBuf, &'a LockedPackage> {
+    lock.packages
+        .iter()
+        .filter_map(|package| {
+            let path = match package.source {
+                Source::Index => {
+                    let key = (package.name.clone(), either::Left(package.version.clone()));
+                    pkgs.get(&key).map(|(_, full_path)| full_path.clone())?
+                }
+                Source::Git => {
+                    let key = (package.name.clone(), either::Right(package.rev.clone()?));
+                    pkgs.get(&key).map(|(_, full_path)| full_path.clone())?
+                }
+                Source::Local => ws_path.join(package.path.as_ref()?),
+            };
+            Some((path.canonicalize().ok()?, package))
+        })
+        .collect()
+}
+
+/// Where the packages in the `[Patch]` section of the manifest are.
+/// Patched git packages are on disk like other git packages.
+fn patches(ws_path: &Path, manifest: &Manifest, pkgs: &Packages) -> Patches {
+    manifest
+        .patch
+        .iter()
+        .filter_map(|(name, patch)| match patch {
+            manifest::Patch::Git(_) => pkgs
+                .iter()
+                .find(|((pkg, version), _)| pkg == name && version.is_right())
+                .map(|((_, version), paths)| {
+                    (name.clone(), (Some(version.clone()), paths.clone()))
+                }),
+            manifest::Patch::Local(local) => Some((
+                name.clone(),
+                (None, (local.path.clone(), ws_path.join(&local.path))),
+            )),
+        })
+        .collect()
+}
+
+/// Finds the package a dependency is connected to. Indexed dependencies use
+/// the version recorded in the lock, so that every dependent gets the version
+/// its requirement was resolved to, even if several major versions are used.
+/// Packages without a lock entry get the latest matching version.
+fn locked_dependency<'a>(
+    pkgs: &'a Packages,
+    locked: Option<&LockedPackage>,
+    name: &str,
+    dep: &manifest::Dependency,
+) -> Option<(&'a PackageKey, &'a (PathBuf, PathBuf))> {
+    let req = match dep {
+        manifest::Dependency::Simple(req) => req,
+        manifest::Dependency::Advanced(dep) => &dep.req,
+        manifest::Dependency::Git(_) => {
+            return pkgs
+                .iter()
+                .find(|((pkg, version), _)| pkg == name && version.is_right());
+        }
+        manifest::Dependency::Local(_) => return None,
+    };
+
+    let version = match locked {
+        Some(locked) => locked
+            .dependencies
+            .iter()
+            .filter_map(|dep| dep.split_once(' '))
+            .find(|(dep, _)| *dep == name)
+            .and_then(|(_, version)| Version::parse(version).ok())
+            .filter(|version| req.matches(version))?,
+        None => pkgs
+            .keys()
+            .filter(|(pkg, _)| pkg == name)
+            .filter_map(|(_, version)| version.as_ref().left())
+            .filter(|version| req.matches(version))
+            .max()?
+            .clone(),
+    };
+
+    pkgs.get_key_value(&(name.to_owned(), either::Left(version)))
+}
+
 fn enable_feature(
     path: (&Path, &Path),
     feature: &str,
@@ -635,4 +965,75 @@ struct SharedConnectData<'a> {
     pub ws: &'a mut beef::BeefSpace,
     pub ws_package_folder: &'a mut HashSet<String>,
     pub connects: &'a mut HashMap<PathBuf, String>,
+    /// Lock entries by canonical package path.
+    pub locked: &'a HashMap<PathBuf, &'a LockedPackage>,
+    pub patches: &'a Patches,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, str::FromStr};
+
+    use either::Either;
+    use maplit::hashmap;
+    use semver::{Version, VersionReq};
+
+    use super::Packages;
+    use crate::{
+        lock::{LockedPackage, Source},
+        manifest::Dependency,
+    };
+
+    fn locked(name: &str, dependencies: &[&str]) -> LockedPackage {
+        LockedPackage {
+            name: name.to_owned(),
+            version: Version::new(1, 0, 0),
+            source: Source::Index,
+            url: None,
+            rev: None,
+            branch: None,
+            tag: None,
+            checksum: None,
+            path: None,
+            dependencies: dependencies.iter().map(|dep| dep.to_string()).collect(),
+        }
+    }
+
+    fn connected(
+        pkgs: &Packages,
+        locked: Option<&LockedPackage>,
+        req: &str,
+    ) -> Option<Either<Version, String>> {
+        let dep = Dependency::Simple(VersionReq::from_str(req).unwrap());
+        super::locked_dependency(pkgs, locked, "x", &dep).map(|((_, version), _)| version.clone())
+    }
+
+    #[test]
+    fn multiple_majors() {
+        let path = |ident: &str| (PathBuf::from(ident), PathBuf::from(ident));
+        let pkgs: Packages = hashmap! {
+            (String::from("x"), Either::Left(Version::new(1, 2, 0))) => path("x-1.2.0"),
+            (String::from("x"), Either::Left(Version::new(2, 0, 0))) => path("x-2.0.0"),
+        };
+        let v1 = Some(Either::Left(Version::new(1, 2, 0)));
+        let v2 = Some(Either::Left(Version::new(2, 0, 0)));
+
+        // Each dependent is connected to the version in its lock entry,
+        // even if the requirement matches both majors
+        let root = locked("a", &["x 1.2.0", "y 1.0.0"]);
+        let y = locked("y", &["x 2.0.0"]);
+        assert_eq!(connected(&pkgs, Some(&root), "*"), v1);
+        assert_eq!(connected(&pkgs, Some(&y), "*"), v2);
+        assert_eq!(connected(&pkgs, Some(&root), "^1"), v1);
+        assert_eq!(connected(&pkgs, Some(&y), "^2"), v2);
+
+        // A lock entry that doesn't satisfy the requirement is not used
+        assert_eq!(connected(&pkgs, Some(&y), "^1"), None);
+        assert_eq!(connected(&pkgs, Some(&locked("z", &[])), "*"), None);
+
+        // Without a lock entry, the latest matching version is used
+        assert_eq!(connected(&pkgs, None, "*"), v2);
+        assert_eq!(connected(&pkgs, None, "^1"), v1);
+        assert_eq!(connected(&pkgs, None, "^3"), None);
+    }
 }
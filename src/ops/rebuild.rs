@@ -6,10 +6,10 @@ use std::{
 use anyhow::{bail, Result};
 use indicatif::ProgressBar;
 
-use super::{beefbuild, make};
+use super::{beefbuild, make, MakeOptions};
 use crate::manifest::Manifest;
 
-pub fn rebuild<P>(path: P, progress: Option<&ProgressBar>) -> Result<()>
+pub fn rebuild<P>(path: P, locked: bool, progress: Option<&ProgressBar>) -> Result<()>
 where
     P: AsRef<Path>,
 {
@@ -21,7 +21,14 @@ where
             progress.set_prefix(console::style("Make").bright().cyan().to_string());
         }
 
-        make(&buildscript_path, true)?;
+        make(
+            &buildscript_path,
+            &MakeOptions {
+                quiet: true,
+                locked,
+                ..Default::default()
+            },
+        )?;
 
         let output = if let Some(progress) = progress {
             progress.set_prefix(console::style("Compile").bright().cyan().to_string());
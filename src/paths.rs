@@ -1,10 +1,22 @@
 use std::{
-    env, fs,
+    env,
+    fs::{self, File},
     path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
 };
 
+use anyhow::{Context, Result};
+
 pub const MANIFEST_FILENAME: &str = "Package.toml";
 pub const LOCK_FILENAME: &str = "Package.lock";
+pub const CONFIG_FILENAME: &str = "config.toml";
+pub const INDEX_FILENAME: &str = "index.toml";
+pub const INSTALLED_FILENAME: &str = ".grill-installed";
 
 pub fn pkg<P>(ws: &P, pkg: &P) -> PathBuf
 where
@@ -24,12 +36,143 @@ pub fn tmp() -> PathBuf {
     ensure_exists(home().join("tmp"))
 }
 
-pub fn index() -> PathBuf {
-    home().join("index.toml")
+/// A unique directory in `~/.grill/tmp` that is removed when dropped,
+/// so that operations and grill processes don't share temporary files.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Result<Self> {
+        Self::new_in(&tmp())
+    }
+
+    /// A unique directory in `dir` instead of `~/.grill/tmp`.
+    pub fn new_in(dir: &Path) -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = dir.join(format!(
+            "{}-{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        // Left behind by a process with the same id that didn't exit cleanly
+        rm_rf::ensure_removed(&path)?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = rm_rf::ensure_removed(&self.path) {
+            log::debug!("Failed to remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Removes temporary directories that processes which crashed left behind.
+pub fn clean_tmp() -> Result<()> {
+    const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+    for entry in fs::read_dir(tmp())? {
+        let entry = entry?;
+        let modified = entry.metadata().and_then(|metadata| metadata.modified());
+        // Another process may have removed it already
+        if let Ok(modified) = modified {
+            if modified.elapsed().unwrap_or_default() > MAX_AGE {
+                rm_rf::ensure_removed(entry.path())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Holds the lock on a directory until it is dropped. See [`lock`].
+#[must_use]
+pub struct DirLock {
+    file: File,
+    path: PathBuf,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        if let Err(err) = self.file.unlock() {
+            log::debug!("Failed to unlock {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Locks `~/.grill` so that grill processes don't change the index at
+/// the same time. See [`lock`].
+pub fn lock_home() -> Result<DirLock> {
+    lock(&home())
 }
 
-pub fn token() -> PathBuf {
-    home().join("token")
+/// Locks `dir` so that grill processes don't change it at the same time.
+/// The lock is held until the returned guard is dropped, so only hold it
+/// while changing the directory, and don't take another lock meanwhile.
+pub fn lock(dir: &Path) -> Result<DirLock> {
+    // Threads of this process wait here instead of on the file
+    static LOCK: Mutex<()> = Mutex::new(());
+    let guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let path = dir.join(".lock");
+    let file = File::create(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            log::info!("Waiting for another grill process to finish");
+            file.lock()
+                .with_context(|| format!("Failed to lock {}", path.display()))?;
+        }
+        Err(fs::TryLockError::Error(err)) => {
+            return Err(err).with_context(|| format!("Failed to lock {}", path.display()))
+        }
+    }
+
+    Ok(DirLock {
+        file,
+        path,
+        _guard: guard,
+    })
+}
+
+pub fn cache() -> PathBuf {
+    ensure_exists(home().join("cache"))
+}
+
+pub fn index(registry: &str) -> PathBuf {
+    ensure_exists(home().join("index").join(registry))
+}
+
+pub fn index_state() -> PathBuf {
+    home().join("index-state.toml")
+}
+
+pub fn token(registry: &str) -> PathBuf {
+    if registry == crate::config::DEFAULT_REGISTRY {
+        home().join("token")
+    } else {
+        home().join(format!("token-{}", registry))
+    }
+}
+
+pub fn config() -> PathBuf {
+    home().join(CONFIG_FILENAME)
+}
+
+pub fn ws_config<P>(ws: &P) -> PathBuf
+where
+    P: AsRef<Path> + ?Sized,
+{
+    ws.as_ref().join(".grill").join(CONFIG_FILENAME)
 }
 
 pub fn home() -> PathBuf {
@@ -1,264 +1,484 @@
-use anyhow::{anyhow, Result};
-use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
 use semver::{Version, VersionReq};
 
-use crate::{
-    index::Index,
-    lock::Lock,
-    manifest::{self, Manifest},
-};
+use crate::{index::Index, manifest::Manifest};
+
+#[cfg(test)]
+mod backtracking;
+mod pubgrub;
+
+/// The selected versions of each indexed package.
+pub type Resolution = HashMap<String, HashSet<Version>>;
+
+/// The version each requirement on an indexed package was resolved to,
+/// by package name and requirement.
+pub type Choices = HashMap<(String, VersionReq), Version>;
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Manifests of the git and local dependencies reachable from the root,
+    /// by dependency name. Their indexed dependencies are resolved as well.
+    pub sources: HashMap<String, Manifest>,
+    /// Indexed packages that are replaced by the source of the same name.
+    pub patches: HashSet<String>,
+    pub strategy: Strategy,
+}
+
+/// Which version is picked when several satisfy the requirements.
+/// Locked versions are always preferred.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    #[default]
+    Highest,
+    /// The lowest version satisfying each requirement, to check
+    /// that the declared lower bounds actually work.
+    Lowest,
+}
+
+/// Resolve the dependencies of a manifest. Versions in the previous
+/// resolution are preferred, if they still satisfy the requirements.
+pub fn resolve(
+    manifest: &Manifest,
+    lock: Option<&Resolution>,
+    index: &Index,
+) -> Result<Resolution> {
+    resolve_with(manifest, lock, index, &Options::default()).map(|(resolution, _)| resolution)
+}
 
-pub fn resolve(manifest: &Manifest, lock: Option<&Lock>, index: &Index) -> Result<Lock> {
+pub fn resolve_with(
+    manifest: &Manifest,
+    lock: Option<&Resolution>,
+    index: &Index,
+    options: &Options,
+) -> Result<(Resolution, Choices)> {
     log::debug!(
         "Resolving dependency tree{}",
         if lock.is_some() { " with lock" } else { "" }
     );
 
-    log::debug!("Deps: {}", manifest.dependencies.len());
-
-    // Add the root dependencies.
-    let mut candidates: Vec<Candidate> = manifest
-        .dependencies
-        .iter()
-        .filter_map(|(name, dep)| {
-            if let manifest::Dependency::Simple(req) = dep {
-                Some(Candidate::new(name, req, index, lock))
-            } else if let manifest::Dependency::Advanced(adv_dep) = dep {
-                Some(Candidate::new(name, &adv_dep.req, index, lock))
-            } else {
-                None
-            }
-        })
-        .collect();
-
-    // We should not remove any candidates before this index.
-    let root_candidates_len = candidates.len();
-    let mut i = 0;
-    let mut failed = false;
-    let mut complete = false;
-
-    while !complete {
-        while let Some(mut candidate) = candidates.get(i).cloned() {
-            let mut next_version = None;
-            // The available versions are sorted so that the top element
-            // is always the latest version.
-            while let Some(version) = candidate.available_versions.pop() {
-                if !candidates
-                    .iter()
-                    .enumerate()
-                    .filter(|&(idx, c)| c.version.is_some() && idx != i)
-                    .any(|(_, i_candidate)| {
-                        let i_version = i_candidate.version.as_ref().unwrap();
-                        // Two versions are conflicting if they have the same major version,
-                        // but a different minor versions. I.e. '1.2.x' is conflicting with '1.3.x'.
-                        // Different major versions for the same dependency is allowed.
-                        // This is for convenience when using libraries together,
-                        // as different versions can't be mixed.
-                        let is_conflicting = i_candidate.name == candidate.name
-                            && i_version.major == version.major
-                            && i_version != &version;
-
-                        if is_conflicting {
-                            log::debug!("Conflicting with: {} v{}", i_candidate.name, i_version);
-                        }
+    pubgrub::resolve(manifest, lock, index, options)
+}
 
-                        is_conflicting
-                    })
-                {
-                    // This version matches the requirements and does not
-                    // come into conflict with any previously selected versions.
-                    next_version = Some(version);
-                    break;
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr};
 
-            if let Some(next_version) = next_version {
-                let deps = &index
-                    .get(&candidate.name)
-                    .unwrap()
-                    .versions
-                    .get(&next_version)
-                    .unwrap()
-                    .deps;
-
-                candidates.extend(
-                    deps.iter()
-                        .map(|(dep, req)| Candidate::new(dep, req, index, lock)),
-                );
+    use itertools::Itertools;
+    use maplit::{hashmap, hashset};
+    use semver::{Version, VersionReq};
 
-                // "Commit" this candidate to the list of candidates.
-                // Will update the selected versions and the available versions left.
-                candidate.version = Some(next_version.clone());
-                candidates[i] = candidate;
-
-                i += 1;
-            } else {
-                if i >= root_candidates_len {
-                    // There were conflicts while selecting a version for this dependency
-                    // so we invalidate the rest of the candidates by removing them.
-                    // This is important as the dependency tree might change when we
-                    // backtrack, leaving unused dependencies.
-                    candidates.truncate(i);
-                } else if i == 0 {
-                    // There was no more versions left for the first
-                    // dependency that satisfies the requirements.
-                    failed = true;
-                    break;
-                } else {
-                    // We can't remove a root dependency so we just
-                    // unset the version and make sure it is ready for
-                    // picking another version.
-                    candidates[i].version = None;
-                    candidates[i].update_available_versions(index, lock);
-                }
+    use super::Resolution;
+    use crate::{
+        index::{Index, IndexEntry, VersionMetadata},
+        manifest::{Manifest, Package},
+    };
 
-                i -= 1;
-            }
-        }
+    #[test]
+    fn basic() {
+        let (manifest, index) = get_test_data();
 
-        // All combinations have been tried.
-        // This manifest can't be resolved.
-        if failed {
-            break;
-        }
+        simplelog::TermLogger::init(
+            log::LevelFilter::Debug,
+            Default::default(),
+            simplelog::TerminalMode::Stdout,
+            simplelog::ColorChoice::Auto,
+        )
+        .unwrap();
 
-        // When conflicts arise and we remove the invalidated candidates,
-        // some of the previous dependencies might not get re-iterated so
-        // that their dependencies are added as candidates again.
-        // We go over the candidate list again to make sure all dependencies
-        // are present and if not, we add the missing dependencies and restart
-        // resolution loop.
-        let mut missing_dependencies = Vec::new();
-        for candidate in &candidates {
-            let deps = &index
-                .get(&candidate.name)
-                .unwrap()
-                .versions
-                .get(candidate.version.as_ref().unwrap())
-                .unwrap()
-                .deps;
-
-            for (dep, req) in deps {
-                if !candidates
-                    .iter()
-                    .filter(|&c| &c.name == dep)
-                    .any(|c| req.matches(c.version.as_ref().unwrap()))
-                {
-                    missing_dependencies.push((dep, req));
-                }
-            }
-        }
+        super::resolve(&manifest, None, &index).unwrap();
+    }
 
-        if missing_dependencies.is_empty() {
-            // All dependencies are present so the resolution is ready,
-            // so we stop the resolution loop.
-            complete = true;
-        } else {
-            // We need to resolve the missing dependencies.
-            // The resolution loop will continue automatically.
-            log::debug!("{:?}", missing_dependencies);
-            candidates.extend(
-                missing_dependencies
-                    .iter()
-                    .map(|(dep, req)| Candidate::new(dep, req, index, lock)),
+    #[test]
+    fn conflict() {
+        let (mut manifest, index) = get_test_data();
+        manifest.dependencies.remove("d");
+        manifest.dependencies.insert(
+            String::from("c"),
+            crate::manifest::Dependency::Simple(VersionReq::from_str("=1.1").unwrap()),
+        );
+
+        let err = super::resolve(&manifest, None, &index)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("b v1.0.0 requires d =1.0"), "{}", err);
+        assert!(err.contains("c v1.1.0 requires d ^1.1"), "{}", err);
+    }
+
+    #[test]
+    fn yanked() {
+        let (mut manifest, mut index) = get_test_data();
+        manifest.dependencies.remove("b");
+        index
+            .get_mut("d")
+            .unwrap()
+            .versions
+            .get_mut(&Version::new(1, 1, 0))
+            .unwrap()
+            .yanked = true;
+
+        // The yanked d v1.1.0 is skipped, so c v1.1.0 can't be used
+        let lock = super::resolve(&manifest, None, &index).unwrap();
+        assert_eq!(lock["c"], hashset! { Version::new(1, 0, 0) });
+        assert_eq!(lock["d"], hashset! { Version::new(1, 0, 0) });
+
+        // Yanked versions are still used when they are locked
+        let previous_lock = hashmap! {
+            String::from("d") => hashset! { Version::new(1, 1, 0) },
+        };
+        let lock = super::resolve(&manifest, Some(&previous_lock), &index).unwrap();
+        assert_eq!(lock["c"], hashset! { Version::new(1, 1, 0) });
+        assert_eq!(lock["d"], hashset! { Version::new(1, 1, 0) });
+    }
+
+    #[test]
+    fn prerelease() {
+        let (mut manifest, mut index) = get_test_data();
+        manifest.dependencies.remove("b");
+        let d = index.get_mut("d").unwrap();
+        for version in ["1.2.0-beta.1", "2.0.0-alpha.1"] {
+            d.versions.insert(
+                Version::parse(version).unwrap(),
+                VersionMetadata {
+                    rev: String::new(),
+                    yanked: false,
+                    deps: hashmap! {},
+                },
             );
         }
+
+        // Pre-releases are not picked unless they are requested
+        let lock = super::resolve(&manifest, None, &index).unwrap();
+        assert_eq!(lock["d"], hashset! { Version::new(1, 1, 0) });
+
+        manifest.dependencies.insert(
+            String::from("d"),
+            crate::manifest::Dependency::Simple(VersionReq::from_str(">=1.0").unwrap()),
+        );
+        let lock = super::resolve(&manifest, None, &index).unwrap();
+        assert!(lock["d"].iter().all(|v| v.pre.is_empty()));
+
+        // A locked pre-release doesn't satisfy a stable requirement either
+        manifest.dependencies.insert(
+            String::from("d"),
+            crate::manifest::Dependency::Simple(VersionReq::from_str("^1.0").unwrap()),
+        );
+        let previous_lock = hashmap! {
+            String::from("d") => hashset! { Version::parse("1.2.0-beta.1").unwrap() },
+        };
+        let lock = super::resolve(&manifest, Some(&previous_lock), &index).unwrap();
+        assert_eq!(lock["d"], hashset! { Version::new(1, 1, 0) });
+
+        // Asking for a pre-release explicitly
+        manifest.dependencies.insert(
+            String::from("d"),
+            crate::manifest::Dependency::Simple(VersionReq::from_str("^1.2.0-beta.1").unwrap()),
+        );
+        let lock = super::resolve(&manifest, None, &index).unwrap();
+        assert_eq!(
+            lock["d"],
+            hashset! { Version::parse("1.2.0-beta.1").unwrap() }
+        );
     }
 
-    if failed {
-        log::debug!("Resolution failed");
-        return Err(anyhow!("Failed to resolve dependencies"));
+    #[test]
+    fn sources() {
+        let (mut manifest, index) = get_test_data();
+        manifest.dependencies.clear();
+        manifest
+            .dependencies
+            .insert(String::from("e"), toml::from_str(r#"Path = "e""#).unwrap());
+
+        let (mut source, _) = get_test_data();
+        source.package.name = String::from("e");
+        source.dependencies.clear();
+        source.dependencies.insert(
+            String::from("d"),
+            crate::manifest::Dependency::Simple(VersionReq::from_str("=1.0").unwrap()),
+        );
+
+        let options = super::Options {
+            sources: hashmap! { String::from("e") => source },
+            ..Default::default()
+        };
+        let (lock, _) = super::resolve_with(&manifest, None, &index, &options).unwrap();
+        assert_eq!(lock.len(), 1);
+        assert_eq!(lock["d"], hashset! { Version::new(1, 0, 0) });
     }
 
-    let mut lock = Lock::new();
-    lock.reserve(candidates.len());
-    for candidate in candidates {
-        lock.entry(candidate.name)
-            .or_default()
-            .insert(candidate.version.unwrap());
+    #[test]
+    fn patch() {
+        let (mut manifest, index) = get_test_data();
+        manifest.dependencies.remove("b");
+
+        let (mut patch, _) = get_test_data();
+        patch.package.name = String::from("d");
+        patch.package.version = Version::new(1, 5, 0);
+        patch.dependencies.clear();
+
+        // Both the root and c v1.1.0 use the patched d
+        let mut options = super::Options {
+            sources: hashmap! { String::from("d") => patch },
+            patches: hashset! { String::from("d") },
+            ..Default::default()
+        };
+        let (lock, _) = super::resolve_with(&manifest, None, &index, &options).unwrap();
+        assert_eq!(lock.len(), 1);
+        assert_eq!(lock["c"], hashset! { Version::new(1, 1, 0) });
+
+        // The patch has to match the requirements
+        options.sources.get_mut("d").unwrap().package.version = Version::new(2, 0, 0);
+        let err = super::resolve_with(&manifest, None, &index, &options)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("no versions of d match ^1.0"), "{}", err);
     }
 
-    log::debug!("Resolved lock: {:#?}", lock);
-    Ok(lock)
-}
+    #[test]
+    fn minimal_versions() {
+        let (mut manifest, index) = get_test_data();
+        manifest.dependencies.remove("b");
 
-#[derive(Clone)]
-struct Candidate {
-    name: String,
-    req: VersionReq,
-    version: Option<Version>,
-    available_versions: Vec<Version>,
-}
+        let options = super::Options {
+            strategy: super::Strategy::Lowest,
+            ..Default::default()
+        };
+        let (lock, _) = super::resolve_with(&manifest, None, &index, &options).unwrap();
+        assert_eq!(lock["c"], hashset! { Version::new(1, 0, 0) });
+        assert_eq!(lock["d"], hashset! { Version::new(1, 0, 0) });
+
+        // The lowest version still has to satisfy every requirement
+        manifest.dependencies.insert(
+            String::from("c"),
+            crate::manifest::Dependency::Simple(VersionReq::from_str("^1.1").unwrap()),
+        );
+        let (lock, _) = super::resolve_with(&manifest, None, &index, &options).unwrap();
+        assert_eq!(lock["c"], hashset! { Version::new(1, 1, 0) });
+        assert_eq!(lock["d"], hashset! { Version::new(1, 1, 0) });
+    }
+
+    #[test]
+    fn choices() {
+        let (mut manifest, mut index) = get_test_data();
+        manifest.dependencies.remove("b");
+        manifest.dependencies.insert(
+            String::from("c"),
+            crate::manifest::Dependency::Simple(VersionReq::from_str("^1.1").unwrap()),
+        );
+        manifest.dependencies.insert(
+            String::from("d"),
+            crate::manifest::Dependency::Simple(VersionReq::from_str("^2").unwrap()),
+        );
+        let any_d = VersionReq::from_str(">=1.1").unwrap();
+        index
+            .get_mut("c")
+            .unwrap()
+            .versions
+            .get_mut(&Version::new(1, 1, 0))
+            .unwrap()
+            .deps
+            .insert(String::from("d"), any_d.clone());
+        index.get_mut("d").unwrap().versions.insert(
+            Version::new(2, 0, 0),
+            VersionMetadata {
+                rev: String::new(),
+                yanked: false,
+                deps: hashmap! {},
+            },
+        );
+        let choice = |choices: &super::Choices, req: &VersionReq| {
+            choices[&(String::from("d"), req.clone())].clone()
+        };
 
-impl Candidate {
-    pub fn new(name: &str, req: &VersionReq, index: &Index, lock: Option<&Lock>) -> Self {
-        let mut candidate = Self {
-            name: name.to_owned(),
-            req: req.clone(),
-            version: None,
-            available_versions: Vec::new(),
+        // c's requirement matches both majors, but its proxy picks the
+        // lower one, even though d v2.0.0 is selected for the root
+        let options = super::Options {
+            strategy: super::Strategy::Lowest,
+            ..Default::default()
         };
-        candidate.update_available_versions(index, lock);
-        candidate
+        let (lock, choices) = super::resolve_with(&manifest, None, &index, &options).unwrap();
+        assert_eq!(
+            lock["d"],
+            hashset! { Version::new(1, 1, 0), Version::new(2, 0, 0) }
+        );
+        assert_eq!(choice(&choices, &any_d), Version::new(1, 1, 0));
+        assert_eq!(
+            choice(&choices, &VersionReq::from_str("^2").unwrap()),
+            Version::new(2, 0, 0)
+        );
+
+        let (lock, choices) =
+            super::resolve_with(&manifest, None, &index, &Default::default()).unwrap();
+        assert_eq!(lock["d"], hashset! { Version::new(2, 0, 0) });
+        assert_eq!(choice(&choices, &any_d), Version::new(2, 0, 0));
     }
 
-    pub fn update_available_versions(&mut self, index: &Index, lock: Option<&Lock>) {
-        self.available_versions.clear();
-        if let Some(entry) = index.get(&self.name) {
-            self.available_versions.extend(
-                entry
-                    .versions
-                    .keys()
-                    .filter(|v| self.req.matches(v))
-                    .cloned()
-                    .sorted_unstable_by(|v1, v2| v1.cmp(v2)),
+    /// Compares the results against the backtracking resolver on small random indices.
+    #[test]
+    fn random() {
+        for seed in 1..=300u64 {
+            let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let (manifest, index) = random_test_data(&mut rng);
+
+            let expected = super::backtracking::resolve(&manifest, None, &index);
+            let actual = super::resolve(&manifest, None, &index);
+
+            // The strategy only changes which solution is found
+            let options = super::Options {
+                strategy: super::Strategy::Lowest,
+                ..Default::default()
+            };
+            match super::resolve_with(&manifest, None, &index, &options) {
+                Ok((lock, _)) => assert_valid(&manifest, &index, &lock, seed),
+                Err(err) => assert!(
+                    actual.is_err(),
+                    "Seed {}: minimal resolution failed:\n{}",
+                    seed,
+                    err
+                ),
+            }
+
+            match actual {
+                Ok(lock) => assert_valid(&manifest, &index, &lock, seed),
+                Err(err) => assert!(
+                    expected.is_err(),
+                    "Seed {}: backtracking resolved {:?}, but got:\n{}",
+                    seed,
+                    expected.unwrap(),
+                    err
+                ),
+            }
+        }
+    }
+
+    fn assert_valid(manifest: &Manifest, index: &Index, lock: &Resolution, seed: u64) {
+        let matches = |name: &String, req: &VersionReq| {
+            lock.get(name)
+                .is_some_and(|versions| versions.iter().any(|v| req.matches(v)))
+        };
+
+        for (name, req) in manifest.indexed_deps() {
+            assert!(
+                matches(name, req),
+                "Seed {}: {} {} is not locked",
+                seed,
+                name,
+                req
             );
         }
 
-        if let Some(version) = lock
-            .and_then(|lock| lock.get(&self.name))
-            .and_then(|locked_versions| locked_versions.iter().find(|&v| self.req.matches(v)))
-        {
-            // Push the locked version to the top so it gets tried first.
-            self.available_versions.push(version.clone());
+        for (name, versions) in lock {
+            assert_eq!(
+                versions.iter().map(|v| v.major).unique().count(),
+                versions.len(),
+                "Seed {}: several versions of {} with the same major",
+                seed,
+                name
+            );
+
+            for version in versions {
+                for (dep, req) in index[name].versions[version].deps.iter() {
+                    assert!(
+                        matches(dep, req),
+                        "Seed {}: {} v{} requires {} {}, which is not locked",
+                        seed,
+                        name,
+                        version,
+                        dep,
+                        req
+                    );
+                }
+            }
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
+    struct XorShift(u64);
 
-    use maplit::hashmap;
-    use semver::{Version, VersionReq};
+    impl XorShift {
+        fn next(&mut self, max: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % max
+        }
+    }
 
-    use crate::{
-        index::{Index, IndexEntry, VersionMetadata},
-        manifest::{Manifest, Package},
-    };
+    /// Packages only depend on packages after them, so the graph is acyclic.
+    fn random_test_data(rng: &mut XorShift) -> (Manifest, Index) {
+        let names: Vec<String> = (0..2 + rng.next(5)).map(|i| format!("p{}", i)).collect();
+
+        let mut index = Index::new();
+        for (i, name) in names.iter().enumerate() {
+            let mut versions = HashMap::new();
+            for major in 1..=2 {
+                for minor in 0..3 {
+                    if rng.next(3) == 0 {
+                        continue;
+                    }
+
+                    let mut deps = HashMap::new();
+                    for dep in names[i + 1..].iter() {
+                        if rng.next(3) == 0 {
+                            deps.insert(dep.clone(), random_req(rng));
+                        }
+                    }
+
+                    versions.insert(
+                        Version::new(major, minor, 0),
+                        VersionMetadata {
+                            rev: String::new(),
+                            yanked: false,
+                            deps,
+                        },
+                    );
+                }
+            }
 
-    #[test]
-    fn basic() {
-        let (manifest, index) = get_test_data();
+            index.insert(
+                name.clone(),
+                IndexEntry {
+                    url: url::Url::parse("http://localhost").unwrap(),
+                    metadata: Default::default(),
+                    versions,
+                },
+            );
+        }
 
-        simplelog::TermLogger::init(
-            log::LevelFilter::Debug,
-            Default::default(),
-            simplelog::TerminalMode::Stdout,
-            simplelog::ColorChoice::Auto,
-        )
-        .unwrap();
+        let (mut manifest, _) = get_test_data();
+        manifest.dependencies.clear();
+        for name in names.iter() {
+            if rng.next(2) == 0 {
+                manifest.dependencies.insert(
+                    name.clone(),
+                    crate::manifest::Dependency::Simple(random_req(rng)),
+                );
+            }
+        }
 
-        super::resolve(&manifest, None, &index).unwrap();
+        (manifest, index)
+    }
+
+    fn random_req(rng: &mut XorShift) -> VersionReq {
+        let req = match rng.next(5) {
+            0 => format!("^1.{}", rng.next(3)),
+            1 => format!("=1.{}.0", rng.next(3)),
+            2 => String::from(">=1.0"),
+            3 => String::from("^2.0"),
+            _ => format!("^{}", 1 + rng.next(2)),
+        };
+        VersionReq::from_str(&req).unwrap()
     }
 
     fn get_test_data() -> (Manifest, Index) {
         let index: Index = hashmap! {
             String::from("b") => IndexEntry {
                 url: url::Url::parse("http://localhost").unwrap(),
+                metadata: Default::default(),
                 versions: hashmap! {
                     Version::from_str("1.0.0").unwrap() => VersionMetadata {
                         rev: String::new(),
+                        yanked: false,
                         deps: hashmap! {
                             String::from("d") => VersionReq::from_str("=1.0").unwrap(),
                         }
@@ -267,13 +487,16 @@ mod tests {
             },
             String::from("c") => IndexEntry {
                 url: url::Url::parse("http://localhost").unwrap(),
+                metadata: Default::default(),
                 versions: hashmap! {
                     Version::from_str("1.0.0").unwrap() => VersionMetadata {
                         rev: String::new(),
+                        yanked: false,
                         deps: hashmap! {}
                     },
                     Version::from_str("1.1.0").unwrap() => VersionMetadata {
                         rev: String::new(),
+                        yanked: false,
                         deps: hashmap! {
                             String::from("d") => VersionReq::from_str("1.1").unwrap(),
                         }
@@ -282,13 +505,16 @@ mod tests {
             },
             String::from("d") => IndexEntry {
                 url: url::Url::parse("http://localhost").unwrap(),
+                metadata: Default::default(),
                 versions: hashmap! {
                     Version::from_str("1.0.0").unwrap() => VersionMetadata {
                         rev: String::new(),
+                        yanked: false,
                         deps: hashmap! {}
                     },
                     Version::from_str("1.1.0").unwrap() => VersionMetadata {
                         rev: String::new(),
+                        yanked: false,
                         deps: hashmap! {}
                     },
                 }
@@ -301,6 +527,13 @@ mod tests {
                 version: Version::new(1, 0, 0),
                 description: String::from(""),
                 corlib: true,
+                authors: Vec::new(),
+                license: None,
+                repository: None,
+                homepage: None,
+                keywords: Vec::new(),
+                categories: Vec::new(),
+                readme: None,
             },
             buildscript: None,
             dependencies: hashmap! {
@@ -309,6 +542,7 @@ mod tests {
                 String::from("d") => crate::manifest::Dependency::Simple(VersionReq::from_str("1.0").unwrap()),
             },
             features: Default::default(),
+            patch: Default::default(),
         };
 
         (manifest, index)
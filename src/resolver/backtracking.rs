@@ -0,0 +1,347 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use semver::{Version, VersionReq};
+
+use super::Resolution;
+use crate::{
+    index::Index,
+    manifest::{self, Manifest},
+};
+
+/// The original resolver, which tries every combination of candidate versions
+/// by backtracking. It is replaced by the PubGrub resolver, but is kept to
+/// compare results against.
+pub fn resolve(
+    manifest: &Manifest,
+    lock: Option<&Resolution>,
+    index: &Index,
+) -> Result<Resolution> {
+    log::debug!(
+        "Resolving dependency tree{}",
+        if lock.is_some() { " with lock" } else { "" }
+    );
+
+    log::debug!("Deps: {}", manifest.dependencies.len());
+
+    let root = format!("{} v{}", manifest.package.name, manifest.package.version);
+
+    // Add the root dependencies.
+    let mut candidates: Vec<Candidate> = manifest
+        .dependencies
+        .iter()
+        .filter_map(|(name, dep)| {
+            if let manifest::Dependency::Simple(req) = dep {
+                Some(Candidate::new(name, req, &root, index, lock))
+            } else if let manifest::Dependency::Advanced(adv_dep) = dep {
+                Some(Candidate::new(name, &adv_dep.req, &root, index, lock))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    // Requirements that couldn't be satisfied while backtracking,
+    // used to explain why the resolution failed.
+    let mut conflicts: Vec<Conflict> = Vec::new();
+
+    // We should not remove any candidates before this index.
+    let root_candidates_len = candidates.len();
+    let mut i = 0;
+    let mut failed = false;
+    let mut complete = false;
+
+    while !complete {
+        while let Some(mut candidate) = candidates.get(i).cloned() {
+            let mut next_version = None;
+            // The available versions are sorted so that the top element
+            // is always the latest version.
+            while let Some(version) = candidate.available_versions.pop() {
+                if !candidates
+                    .iter()
+                    .enumerate()
+                    .filter(|&(idx, c)| c.version.is_some() && idx != i)
+                    .any(|(_, i_candidate)| {
+                        let i_version = i_candidate.version.as_ref().unwrap();
+                        // Two versions are conflicting if they have the same major version,
+                        // but a different minor versions. I.e. '1.2.x' is conflicting with '1.3.x'.
+                        // Different major versions for the same dependency is allowed.
+                        // This is for convenience when using libraries together,
+                        // as different versions can't be mixed.
+                        let is_conflicting = i_candidate.name == candidate.name
+                            && i_version.major == version.major
+                            && i_version != &version;
+
+                        if is_conflicting {
+                            log::debug!("Conflicting with: {} v{}", i_candidate.name, i_version);
+                        }
+
+                        is_conflicting
+                    })
+                {
+                    // This version matches the requirements and does not
+                    // come into conflict with any previously selected versions.
+                    next_version = Some(version);
+                    break;
+                }
+            }
+
+            if let Some(next_version) = next_version {
+                let deps = &index
+                    .get(&candidate.name)
+                    .unwrap()
+                    .versions
+                    .get(&next_version)
+                    .unwrap()
+                    .deps;
+
+                let required_by = format!("{} v{}", candidate.name, next_version);
+                candidates.extend(
+                    deps.iter()
+                        .map(|(dep, req)| Candidate::new(dep, req, &required_by, index, lock)),
+                );
+
+                // "Commit" this candidate to the list of candidates.
+                // Will update the selected versions and the available versions left.
+                candidate.version = Some(next_version.clone());
+                candidates[i] = candidate;
+
+                i += 1;
+            } else {
+                if let Some(conflict) = Conflict::find(&candidates, i, index) {
+                    if !conflicts.contains(&conflict) {
+                        log::debug!("{}", conflict);
+                        conflicts.push(conflict);
+                    }
+                }
+
+                if i >= root_candidates_len {
+                    // There were conflicts while selecting a version for this dependency
+                    // so we invalidate the rest of the candidates by removing them.
+                    // This is important as the dependency tree might change when we
+                    // backtrack, leaving unused dependencies.
+                    candidates.truncate(i);
+                } else if i == 0 {
+                    // There was no more versions left for the first
+                    // dependency that satisfies the requirements.
+                    failed = true;
+                    break;
+                } else {
+                    // We can't remove a root dependency so we just
+                    // unset the version and make sure it is ready for
+                    // picking another version.
+                    candidates[i].version = None;
+                    candidates[i].update_available_versions(index, lock);
+                }
+
+                i -= 1;
+            }
+        }
+
+        // All combinations have been tried.
+        // This manifest can't be resolved.
+        if failed {
+            break;
+        }
+
+        // When conflicts arise and we remove the invalidated candidates,
+        // some of the previous dependencies might not get re-iterated so
+        // that their dependencies are added as candidates again.
+        // We go over the candidate list again to make sure all dependencies
+        // are present and if not, we add the missing dependencies and restart
+        // resolution loop.
+        let mut missing_dependencies = Vec::new();
+        for candidate in &candidates {
+            let required_by = format!(
+                "{} v{}",
+                candidate.name,
+                candidate.version.as_ref().unwrap()
+            );
+            let deps = &index
+                .get(&candidate.name)
+                .unwrap()
+                .versions
+                .get(candidate.version.as_ref().unwrap())
+                .unwrap()
+                .deps;
+
+            for (dep, req) in deps {
+                if !candidates
+                    .iter()
+                    .filter(|&c| &c.name == dep)
+                    .any(|c| req.matches(c.version.as_ref().unwrap()))
+                {
+                    missing_dependencies.push((dep, req, required_by.clone()));
+                }
+            }
+        }
+
+        if missing_dependencies.is_empty() {
+            // All dependencies are present so the resolution is ready,
+            // so we stop the resolution loop.
+            complete = true;
+        } else {
+            // We need to resolve the missing dependencies.
+            // The resolution loop will continue automatically.
+            log::debug!("{:?}", missing_dependencies);
+            candidates.extend(
+                missing_dependencies.iter().map(|(dep, req, required_by)| {
+                    Candidate::new(dep, req, required_by, index, lock)
+                }),
+            );
+        }
+    }
+
+    if failed {
+        log::debug!("Resolution failed");
+        return Err(anyhow!(
+            "Failed to resolve dependencies:\n{}",
+            conflicts.iter().join("\n\n")
+        ));
+    }
+
+    let mut lock = Resolution::new();
+    lock.reserve(candidates.len());
+    for candidate in candidates {
+        lock.entry(candidate.name)
+            .or_default()
+            .insert(candidate.version.unwrap());
+    }
+
+    log::debug!("Resolved lock: {:#?}", lock);
+    Ok(lock)
+}
+
+#[derive(Clone)]
+struct Candidate {
+    name: String,
+    req: VersionReq,
+    /// The package (and version) that added this requirement.
+    required_by: String,
+    version: Option<Version>,
+    available_versions: Vec<Version>,
+}
+
+impl Candidate {
+    pub fn new(
+        name: &str,
+        req: &VersionReq,
+        required_by: &str,
+        index: &Index,
+        lock: Option<&Resolution>,
+    ) -> Self {
+        let mut candidate = Self {
+            name: name.to_owned(),
+            req: req.clone(),
+            required_by: required_by.to_owned(),
+            version: None,
+            available_versions: Vec::new(),
+        };
+        candidate.update_available_versions(index, lock);
+        candidate
+    }
+
+    pub fn update_available_versions(&mut self, index: &Index, lock: Option<&Resolution>) {
+        self.available_versions.clear();
+        if let Some(entry) = index.get(&self.name) {
+            self.available_versions.extend(
+                entry
+                    .versions
+                    .iter()
+                    .filter(|(v, metadata)| !metadata.yanked && self.req.matches(v))
+                    .map(|(v, _)| v)
+                    .cloned()
+                    .sorted_unstable_by(|v1, v2| v1.cmp(v2)),
+            );
+        }
+
+        if let Some(version) = lock
+            .and_then(|lock| lock.get(&self.name))
+            .and_then(|locked_versions| locked_versions.iter().find(|&v| self.req.matches(v)))
+        {
+            // Push the locked version to the top so it gets tried first.
+            // Locked versions are used even if they have been yanked.
+            self.available_versions.push(version.clone());
+        }
+    }
+}
+
+/// A requirement that had no versions left, along with the
+/// requirements of the selected versions it conflicted with.
+#[derive(Debug, PartialEq)]
+struct Conflict {
+    name: String,
+    req: VersionReq,
+    required_by: String,
+    conflicting: Vec<(String, VersionReq, Version)>,
+}
+
+impl Conflict {
+    /// Find out why the candidate at `i` has no versions left. Returns `None` if the
+    /// candidate only ran out of versions because they were tried while backtracking.
+    fn find(candidates: &[Candidate], i: usize, index: &Index) -> Option<Conflict> {
+        let candidate = &candidates[i];
+        let matching_majors: Vec<u64> = index
+            .get(&candidate.name)
+            .map(|entry| {
+                entry
+                    .versions
+                    .iter()
+                    .filter(|(v, metadata)| !metadata.yanked && candidate.req.matches(v))
+                    .map(|(v, _)| v.major)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let conflicting: Vec<(String, VersionReq, Version)> = candidates
+            .iter()
+            .enumerate()
+            .filter(|&(idx, c)| idx != i && c.name == candidate.name)
+            .filter_map(|(_, c)| {
+                c.version
+                    .as_ref()
+                    .filter(|v| matching_majors.contains(&v.major))
+                    .map(|v| (c.required_by.clone(), c.req.clone(), v.clone()))
+            })
+            .collect();
+
+        if conflicting.is_empty() && !matching_majors.is_empty() {
+            return None;
+        }
+
+        Some(Conflict {
+            name: candidate.name.clone(),
+            req: candidate.req.clone(),
+            required_by: candidate.required_by.clone(),
+            conflicting,
+        })
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requires {} {}",
+            self.required_by, self.name, self.req
+        )?;
+        if self.conflicting.is_empty() {
+            write!(
+                f,
+                "\n    but no versions of {} match {}",
+                self.name, self.req
+            )?;
+        }
+
+        for (required_by, req, version) in self.conflicting.iter() {
+            write!(
+                f,
+                "\n    but {} requires {} {} (selected v{})",
+                required_by, self.name, req, version
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,862 @@
+//! A conflict-driven resolver based on PubGrub:
+//! https://github.com/dart-lang/pub/blob/master/doc/solver.md
+//!
+//! Only one version of each major version of a package can be selected,
+//! so every major version is treated as a separate package by the solver.
+//! Requirements that match several major versions depend on a proxy
+//! package, whose versions are the major versions to pick from.
+//! Git and local dependencies are packages with a single version,
+//! so their own dependencies are resolved together with the rest.
+//! Patched packages are source packages that every requirement on the
+//! indexed package of the same name resolves to.
+//!
+//! Pre-releases are only selected when a requirement asks for them
+//! explicitly, following the `VersionReq::matches` rules: `^1.2.0-beta.1`
+//! allows pre-releases of 1.2.0, but `^1.0` or `>=1.0` allow none.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    iter,
+};
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use semver::{Version, VersionReq};
+
+use super::{Choices, Options, Resolution, Strategy};
+use crate::{index::Index, manifest::Manifest};
+
+pub fn resolve(
+    manifest: &Manifest,
+    lock: Option<&Resolution>,
+    index: &Index,
+    options: &Options,
+) -> Result<(Resolution, Choices)> {
+    let mut solver = Solver {
+        manifest,
+        lock,
+        index,
+        sources: &options.sources,
+        patches: &options.patches,
+        strategy: options.strategy,
+        incompatibilities: Vec::new(),
+        incompatibilities_by_package: Default::default(),
+        assignments: Vec::new(),
+        level: 0,
+        added_dependencies: HashSet::new(),
+    };
+
+    if let Err(failure) = solver.solve() {
+        log::debug!("Resolution failed");
+        return Err(anyhow!(
+            "Failed to resolve dependencies:\n{}",
+            solver.explain(failure)
+        ));
+    }
+
+    let mut lock = Resolution::new();
+    for assignment in solver.assignments.iter() {
+        if let (None, Package::Major(name, _)) = (assignment.cause, &assignment.package) {
+            lock.entry(name.clone())
+                .or_default()
+                .extend(assignment.term.versions.iter().cloned());
+        }
+    }
+
+    log::debug!("Resolved lock: {:#?}", lock);
+    Ok((lock, solver.choices()))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Package {
+    Root,
+    /// The versions of a package with the same major version.
+    Major(String, u64),
+    /// A requirement that matches several major versions of a package.
+    /// The versions are the majors as `major.0.0`.
+    Proxy(String, VersionReq),
+    /// A git or local dependency, which only has the version in its manifest.
+    Source(String),
+}
+
+impl Package {
+    fn sort_key(&self) -> (u8, &str, u64, String) {
+        match self {
+            Package::Root => (0, "", 0, String::new()),
+            Package::Major(name, major) => (1, name, *major, String::new()),
+            Package::Proxy(name, req) => (2, name, 0, req.to_string()),
+            Package::Source(name) => (3, name, 0, String::new()),
+        }
+    }
+}
+
+type VersionSet = BTreeSet<Version>;
+
+/// The versions a package can be selected at. If `none` is set,
+/// the package is also allowed to not be selected at all.
+///
+/// All versions are a subset of the package's available versions,
+/// so the complement of a term is relative to those.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Term {
+    versions: VersionSet,
+    none: bool,
+}
+
+impl Term {
+    fn positive(versions: VersionSet) -> Term {
+        Term {
+            versions,
+            none: false,
+        }
+    }
+
+    fn any(universe: &VersionSet) -> Term {
+        Term {
+            versions: universe.clone(),
+            none: true,
+        }
+    }
+
+    fn is_positive(&self) -> bool {
+        !self.none
+    }
+
+    fn negate(&self, universe: &VersionSet) -> Term {
+        Term {
+            versions: universe.difference(&self.versions).cloned().collect(),
+            none: !self.none,
+        }
+    }
+
+    fn intersection(&self, other: &Term) -> Term {
+        Term {
+            versions: self
+                .versions
+                .intersection(&other.versions)
+                .cloned()
+                .collect(),
+            none: self.none && other.none,
+        }
+    }
+
+    fn union(&self, other: &Term) -> Term {
+        Term {
+            versions: self.versions.union(&other.versions).cloned().collect(),
+            none: self.none || other.none,
+        }
+    }
+
+    fn is_subset_of(&self, other: &Term) -> bool {
+        self.versions.is_subset(&other.versions) && (!self.none || other.none)
+    }
+
+    fn is_disjoint(&self, other: &Term) -> bool {
+        self.versions.is_disjoint(&other.versions) && !(self.none && other.none)
+    }
+}
+
+/// A set of terms that can't all be true at the same time.
+struct Incompatibility {
+    terms: Vec<(Package, Term)>,
+    cause: Cause,
+}
+
+enum Cause {
+    /// The root package has to be selected.
+    Root,
+    /// The first term depends on the negation of the second term.
+    Dependency {
+        depender: String,
+        name: String,
+        req: VersionReq,
+    },
+    /// A proxy depends on the major version it selected.
+    Proxy,
+    /// No versions of the package are available.
+    NoVersions,
+    /// Derived from two other incompatibilities during conflict resolution.
+    Derived(usize, usize),
+}
+
+struct Assignment {
+    package: Package,
+    term: Term,
+    level: usize,
+    /// The incompatibility this was derived from, or `None` for decisions.
+    cause: Option<usize>,
+}
+
+enum Relation {
+    Satisfied,
+    Contradicted,
+    AlmostSatisfied(Package),
+    Inconclusive,
+}
+
+struct Solver<'a> {
+    manifest: &'a Manifest,
+    lock: Option<&'a Resolution>,
+    index: &'a Index,
+    sources: &'a HashMap<String, Manifest>,
+    patches: &'a HashSet<String>,
+    strategy: Strategy,
+    incompatibilities: Vec<Incompatibility>,
+    incompatibilities_by_package: HashMap<Package, Vec<usize>>,
+    /// The partial solution.
+    assignments: Vec<Assignment>,
+    level: usize,
+    added_dependencies: HashSet<(Package, Version)>,
+}
+
+impl Solver<'_> {
+    /// Runs until every required package has been decided. On failure, the
+    /// incompatibility proving that there is no solution is returned.
+    fn solve(&mut self) -> Result<(), usize> {
+        let root = self.universe(&Package::Root);
+        let id = self.add_incompatibility(
+            vec![(Package::Root, Term::positive(root.clone()).negate(&root))],
+            Cause::Root,
+        );
+        self.register(id);
+
+        let mut next = Package::Root;
+        loop {
+            self.propagate(next)?;
+
+            let (package, term) = match self.next_package() {
+                Some(next) => next,
+                None => return Ok(()),
+            };
+
+            let version = match self.choose_version(&package, &term) {
+                Some(version) => version,
+                None => {
+                    let id =
+                        self.add_incompatibility(vec![(package.clone(), term)], Cause::NoVersions);
+                    self.register(id);
+                    next = package;
+                    continue;
+                }
+            };
+
+            log::debug!("Trying {:?} v{}", package, version);
+            let dependencies = self.add_dependencies(&package, &version);
+
+            self.level += 1;
+            self.assignments.push(Assignment {
+                package: package.clone(),
+                term: Term::positive(iter::once(version).collect()),
+                level: self.level,
+                cause: None,
+            });
+
+            // Don't make the decision if it conflicts with its own dependencies.
+            // Propagation will then rule the version out.
+            if dependencies
+                .iter()
+                .any(|&id| matches!(self.relation(id), Relation::Satisfied))
+            {
+                self.assignments.pop();
+                self.level -= 1;
+            }
+
+            next = package;
+        }
+    }
+
+    fn propagate(&mut self, package: Package) -> Result<(), usize> {
+        let mut changed = vec![package];
+        while let Some(package) = changed.pop() {
+            let ids = self
+                .incompatibilities_by_package
+                .get(&package)
+                .cloned()
+                .unwrap_or_default();
+
+            for &id in ids.iter().rev() {
+                match self.relation(id) {
+                    Relation::Satisfied => {
+                        let root_cause = self.resolve_conflict(id)?;
+                        match self.relation(root_cause) {
+                            Relation::AlmostSatisfied(package) => {
+                                self.derive(root_cause, &package);
+                                changed.clear();
+                                changed.push(package);
+                            }
+                            _ => return Err(root_cause),
+                        }
+                        break;
+                    }
+                    Relation::AlmostSatisfied(package) => {
+                        self.derive(id, &package);
+                        if !changed.contains(&package) {
+                            changed.push(package);
+                        }
+                    }
+                    Relation::Contradicted | Relation::Inconclusive => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Learns a new incompatibility from a conflict and backtracks until it is
+    /// no longer satisfied. Returns the learned incompatibility.
+    fn resolve_conflict(&mut self, mut id: usize) -> Result<usize, usize> {
+        let mut created = false;
+        loop {
+            if self.is_failure(id) {
+                return Err(id);
+            }
+
+            let terms = self.incompatibilities[id].terms.clone();
+            let satisfiers: Vec<usize> = terms
+                .iter()
+                .map(|(package, term)| {
+                    self.satisfier(
+                        package,
+                        term,
+                        Term::any(&self.universe(package)),
+                        self.assignments.len(),
+                    )
+                    .expect("Incompatibility is not satisfied")
+                })
+                .collect();
+
+            let (term_idx, &satisfier) = satisfiers
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, &satisfier)| satisfier)
+                .unwrap();
+            let (package, term) = &terms[term_idx];
+            let satisfier_term = self.assignments[satisfier].term.clone();
+            let satisfier_level = self.assignments[satisfier].level;
+            let satisfier_cause = self.assignments[satisfier].cause;
+
+            let mut previous_level = satisfiers
+                .iter()
+                .enumerate()
+                .filter(|&(idx, _)| idx != term_idx)
+                .map(|(_, &satisfier)| self.assignments[satisfier].level)
+                .max()
+                .unwrap_or(1);
+
+            if !satisfier_term.is_subset_of(term) {
+                if let Some(previous) =
+                    self.satisfier(package, term, satisfier_term.clone(), satisfier)
+                {
+                    previous_level = previous_level.max(self.assignments[previous].level);
+                }
+            }
+            previous_level = previous_level.max(1);
+
+            match satisfier_cause {
+                Some(cause) if previous_level >= satisfier_level => {
+                    let terms = self.prior_cause(id, cause, package);
+                    id = self.add_incompatibility(terms, Cause::Derived(id, cause));
+                    created = true;
+                }
+                _ => {
+                    self.backtrack(previous_level);
+                    if created {
+                        self.register(id);
+                    }
+                    return Ok(id);
+                }
+            }
+        }
+    }
+
+    /// The terms of both incompatibilities combined, except for `package`,
+    /// which is resolved away.
+    fn prior_cause(&self, id: usize, cause: usize, package: &Package) -> Vec<(Package, Term)> {
+        let mut terms: Vec<(Package, Term)> = Vec::new();
+        for (p, t) in self.incompatibilities[id]
+            .terms
+            .iter()
+            .chain(self.incompatibilities[cause].terms.iter())
+        {
+            if let Some((_, existing)) = terms.iter_mut().find(|(q, _)| q == p) {
+                *existing = if p == package {
+                    existing.union(t)
+                } else {
+                    existing.intersection(t)
+                };
+            } else {
+                terms.push((p.clone(), t.clone()));
+            }
+        }
+
+        terms
+    }
+
+    /// Index of the earliest assignment that, together with `start` and the
+    /// assignments before it, satisfies the term.
+    fn satisfier(&self, package: &Package, term: &Term, start: Term, end: usize) -> Option<usize> {
+        let mut accumulated = start;
+        for (idx, assignment) in self.assignments[..end].iter().enumerate() {
+            if &assignment.package != package {
+                continue;
+            }
+
+            accumulated = accumulated.intersection(&assignment.term);
+            if accumulated.is_subset_of(term) {
+                return Some(idx);
+            }
+        }
+
+        None
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        self.assignments
+            .retain(|assignment| assignment.level <= level);
+        self.level = level;
+    }
+
+    fn derive(&mut self, id: usize, package: &Package) {
+        let (_, term) = self.incompatibilities[id]
+            .terms
+            .iter()
+            .find(|(p, _)| p == package)
+            .unwrap();
+
+        self.assignments.push(Assignment {
+            package: package.clone(),
+            term: term.negate(&self.universe(package)),
+            level: self.level,
+            cause: Some(id),
+        });
+    }
+
+    fn relation(&self, id: usize) -> Relation {
+        let mut unsatisfied = None;
+        for (package, term) in self.incompatibilities[id].terms.iter() {
+            let current = self.term(package);
+            if current.is_subset_of(term) {
+                continue;
+            }
+
+            if current.is_disjoint(term) {
+                return Relation::Contradicted;
+            }
+
+            if unsatisfied.is_some() {
+                return Relation::Inconclusive;
+            }
+            unsatisfied = Some(package.clone());
+        }
+
+        match unsatisfied {
+            Some(package) => Relation::AlmostSatisfied(package),
+            None => Relation::Satisfied,
+        }
+    }
+
+    /// The intersection of all assignments for the package.
+    fn term(&self, package: &Package) -> Term {
+        self.assignments
+            .iter()
+            .filter(|assignment| &assignment.package == package)
+            .fold(Term::any(&self.universe(package)), |term, assignment| {
+                term.intersection(&assignment.term)
+            })
+    }
+
+    fn is_failure(&self, id: usize) -> bool {
+        match self.incompatibilities[id].terms.as_slice() {
+            [] => true,
+            [(Package::Root, term)] => term.is_positive(),
+            _ => false,
+        }
+    }
+
+    /// The package with the fewest versions left out of those that are
+    /// required, but not decided yet.
+    fn next_package(&self) -> Option<(Package, Term)> {
+        let decided: HashSet<&Package> = self
+            .assignments
+            .iter()
+            .filter(|assignment| assignment.cause.is_none())
+            .map(|assignment| &assignment.package)
+            .collect();
+
+        self.assignments
+            .iter()
+            .map(|assignment| &assignment.package)
+            .unique()
+            .filter(|package| !decided.contains(package))
+            .map(|package| (package.clone(), self.term(package)))
+            .filter(|(_, term)| term.is_positive())
+            .min_by(|(p1, t1), (p2, t2)| {
+                t1.versions
+                    .len()
+                    .cmp(&t2.versions.len())
+                    .then_with(|| p1.sort_key().cmp(&p2.sort_key()))
+            })
+    }
+
+    /// Picks the locked version if possible, otherwise the latest
+    /// or the lowest version, depending on the strategy.
+    fn choose_version(&self, package: &Package, term: &Term) -> Option<Version> {
+        let locked = match package {
+            Package::Major(name, _) => term.versions.iter().rev().find(|v| self.is_locked(name, v)),
+            Package::Proxy(name, _) => term.versions.iter().rev().find(|major| {
+                self.locked_versions(name)
+                    .any(|locked| locked.major == major.major)
+            }),
+            Package::Root | Package::Source(_) => None,
+        };
+
+        locked
+            .or_else(|| match self.strategy {
+                Strategy::Highest => term.versions.iter().next_back(),
+                Strategy::Lowest => term.versions.iter().next(),
+            })
+            .cloned()
+    }
+
+    /// Adds the incompatibilities for the dependencies of a package version.
+    /// Returns the ids of the incompatibilities.
+    fn add_dependencies(&mut self, package: &Package, version: &Version) -> Vec<usize> {
+        if !self
+            .added_dependencies
+            .insert((package.clone(), version.clone()))
+        {
+            return Vec::new();
+        }
+
+        let this = (
+            package.clone(),
+            Term::positive(iter::once(version.clone()).collect()),
+        );
+        let deps = match package {
+            Package::Proxy(name, req) => {
+                let major = Package::Major(name.clone(), version.major);
+                let universe = self.universe(&major);
+                let versions = universe
+                    .iter()
+                    .filter(|v| req.matches(v))
+                    .cloned()
+                    .collect();
+                let id = self.add_incompatibility(
+                    vec![this, (major, Term::positive(versions).negate(&universe))],
+                    Cause::Proxy,
+                );
+                self.register(id);
+                return vec![id];
+            }
+            _ => self.indexed_deps(package, version),
+        };
+
+        let sources: Vec<(String, VersionReq)> = match package {
+            Package::Root | Package::Source(_) => {
+                let manifest = self.manifest_of(package);
+                manifest
+                    .local_deps()
+                    .map(|(name, _)| name)
+                    .chain(manifest.git_deps().map(|(name, _)| name))
+                    .filter_map(|name| {
+                        let version = &self.sources.get(name)?.package.version;
+                        let req = VersionReq::parse(&format!("={}", version)).ok()?;
+                        Some((name.clone(), req))
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let depender = self.describe(package, &this.1.versions);
+        deps.into_iter()
+            .map(|(name, req)| (false, name, req))
+            .chain(sources.into_iter().map(|(name, req)| (true, name, req)))
+            .sorted_by(|(_, n1, _), (_, n2, _)| n1.cmp(n2))
+            .map(|(is_source, name, req)| {
+                let (dep, versions) = if is_source {
+                    let dep = Package::Source(name.clone());
+                    let versions = self.universe(&dep);
+                    (dep, versions)
+                } else {
+                    self.requirement(&name, &req)
+                };
+                let universe = self.universe(&dep);
+                let id = self.add_incompatibility(
+                    vec![
+                        this.clone(),
+                        (dep, Term::positive(versions).negate(&universe)),
+                    ],
+                    Cause::Dependency {
+                        depender: depender.clone(),
+                        name,
+                        req,
+                    },
+                );
+                self.register(id);
+                id
+            })
+            .collect()
+    }
+
+    /// The requirements on indexed packages of a package version.
+    /// Proxies have no requirements of their own.
+    fn indexed_deps(&self, package: &Package, version: &Version) -> Vec<(String, VersionReq)> {
+        match package {
+            Package::Root | Package::Source(_) => self
+                .manifest_of(package)
+                .indexed_deps()
+                .map(|(name, req)| (name.clone(), req.clone()))
+                .collect(),
+            Package::Major(name, _) => self.index[name].versions[version]
+                .deps
+                .iter()
+                .map(|(name, req)| (name.clone(), req.clone()))
+                .collect(),
+            Package::Proxy(..) => Vec::new(),
+        }
+    }
+
+    /// The version each requirement of the selected packages resolved to.
+    /// Requirements matching several major versions use the major their
+    /// proxy was decided at.
+    fn choices(&self) -> Choices {
+        let decided: HashMap<&Package, &Version> = self
+            .assignments
+            .iter()
+            .filter(|assignment| assignment.cause.is_none())
+            .filter_map(|assignment| Some((&assignment.package, assignment.term.versions.first()?)))
+            .collect();
+
+        let mut choices = Choices::new();
+        for (package, version) in decided.iter() {
+            for (name, req) in self.indexed_deps(package, version) {
+                let chosen = match self.requirement(&name, &req).0 {
+                    Package::Proxy(_, _) => {
+                        let major = decided.get(&Package::Proxy(name.clone(), req.clone()));
+                        major.and_then(|major| {
+                            decided.get(&Package::Major(name.clone(), major.major))
+                        })
+                    }
+                    dep => decided.get(&dep),
+                };
+                if let Some(chosen) = chosen {
+                    choices.insert((name, req), (*chosen).clone());
+                }
+            }
+        }
+
+        choices
+    }
+
+    /// The package and versions a requirement refers to.
+    fn requirement(&self, name: &str, req: &VersionReq) -> (Package, VersionSet) {
+        if self.patches.contains(name) {
+            let patch = Package::Source(name.to_owned());
+            let versions = self
+                .universe(&patch)
+                .into_iter()
+                .filter(|v| req.matches(v))
+                .collect();
+            return (patch, versions);
+        }
+
+        let matching: VersionSet = self
+            .available_versions(name)
+            .filter(|v| req.matches(v))
+            .cloned()
+            .collect();
+        let majors: BTreeSet<u64> = matching.iter().map(|v| v.major).collect();
+
+        if majors.len() == 1 {
+            let major = *majors.iter().next().unwrap();
+            (Package::Major(name.to_owned(), major), matching)
+        } else {
+            let proxy = Package::Proxy(name.to_owned(), req.clone());
+            let versions = self.universe(&proxy);
+            (proxy, versions)
+        }
+    }
+
+    /// All versions the package can be selected at.
+    fn universe(&self, package: &Package) -> VersionSet {
+        match package {
+            Package::Root | Package::Source(_) => {
+                iter::once(self.manifest_of(package).package.version.clone()).collect()
+            }
+            Package::Major(name, major) => self
+                .available_versions(name)
+                .filter(|v| v.major == *major)
+                .cloned()
+                .collect(),
+            Package::Proxy(name, req) => self
+                .available_versions(name)
+                .filter(|v| req.matches(v))
+                .map(|v| Version::new(v.major, 0, 0))
+                .collect(),
+        }
+    }
+
+    fn manifest_of(&self, package: &Package) -> &Manifest {
+        match package {
+            Package::Source(name) => &self.sources[name],
+            _ => self.manifest,
+        }
+    }
+
+    /// Versions in the index, excluding yanked versions unless they are locked.
+    fn available_versions<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'b Version> {
+        self.index
+            .get(name)
+            .into_iter()
+            .flat_map(|entry| entry.versions.iter())
+            .filter(move |(v, metadata)| !metadata.yanked || self.is_locked(name, v))
+            .map(|(v, _)| v)
+    }
+
+    fn locked_versions<'b>(&'b self, name: &str) -> impl Iterator<Item = &'b Version> {
+        self.lock
+            .and_then(|lock| lock.get(name))
+            .into_iter()
+            .flatten()
+    }
+
+    fn is_locked(&self, name: &str, version: &Version) -> bool {
+        self.locked_versions(name).any(|v| v == version)
+    }
+
+    /// Adds an incompatibility without registering it for propagation.
+    /// Terms that are always satisfied are left out.
+    fn add_incompatibility(&mut self, terms: Vec<(Package, Term)>, cause: Cause) -> usize {
+        let mut merged: Vec<(Package, Term)> = Vec::new();
+        for (package, term) in terms {
+            if let Some((_, existing)) = merged.iter_mut().find(|(p, _)| p == &package) {
+                *existing = existing.intersection(&term);
+            } else {
+                merged.push((package, term));
+            }
+        }
+        merged.retain(|(package, term)| term != &Term::any(&self.universe(package)));
+
+        self.incompatibilities.push(Incompatibility {
+            terms: merged,
+            cause,
+        });
+        self.incompatibilities.len() - 1
+    }
+
+    fn register(&mut self, id: usize) {
+        for (package, _) in self.incompatibilities[id].terms.iter() {
+            self.incompatibilities_by_package
+                .entry(package.clone())
+                .or_default()
+                .push(id);
+        }
+    }
+
+    /// Explain why an incompatibility is true, by walking the derivation tree.
+    fn explain(&self, id: usize) -> String {
+        let mut lines = Vec::new();
+        self.explain_into(id, &mut lines, &mut HashSet::new());
+        lines.join("\n")
+    }
+
+    fn explain_into(&self, id: usize, lines: &mut Vec<String>, visited: &mut HashSet<usize>) {
+        if !visited.insert(id) {
+            return;
+        }
+
+        let incompatibility = &self.incompatibilities[id];
+        match &incompatibility.cause {
+            Cause::Root | Cause::Proxy => (),
+            Cause::Dependency {
+                depender,
+                name,
+                req,
+            } => {
+                lines.push(format!("{} requires {} {}", depender, name, req));
+                if incompatibility.terms.len() == 1 {
+                    lines.push(format!("    but no versions of {} match {}", name, req));
+                }
+            }
+            Cause::NoVersions => lines.push(self.describe_incompatibility(id)),
+            Cause::Derived(first, second) => {
+                self.explain_into(*first, lines, visited);
+                self.explain_into(*second, lines, visited);
+                lines.push(format!("    so {}", self.describe_incompatibility(id)));
+            }
+        }
+    }
+
+    fn describe_incompatibility(&self, id: usize) -> String {
+        let describe_positive = |(package, term): &(Package, Term)| {
+            if term.is_positive() {
+                self.describe(package, &term.versions)
+            } else {
+                format!(
+                    "not {}",
+                    self.describe(package, &term.negate(&self.universe(package)).versions)
+                )
+            }
+        };
+
+        match self.incompatibilities[id].terms.as_slice() {
+            [] => String::from("no versions can be selected"),
+            [(Package::Root, term)] if term.is_positive() => {
+                format!(
+                    "{} can't be resolved",
+                    self.describe(&Package::Root, &term.versions)
+                )
+            }
+            [(package, term)] if term.is_positive() => {
+                format!("{} can't be used", self.describe(package, &term.versions))
+            }
+            [(package, term)] => format!(
+                "{} is required",
+                self.describe(package, &term.negate(&self.universe(package)).versions)
+            ),
+            [(p1, t1), (p2, t2)] if t1.is_positive() != t2.is_positive() => {
+                let (dependant, dependency) = if t1.is_positive() {
+                    ((p1, t1), (p2, t2))
+                } else {
+                    ((p2, t2), (p1, t1))
+                };
+                format!(
+                    "{} requires {}",
+                    self.describe(dependant.0, &dependant.1.versions),
+                    self.describe(
+                        dependency.0,
+                        &dependency.1.negate(&self.universe(dependency.0)).versions
+                    )
+                )
+            }
+            terms => format!(
+                "{} can't be used together",
+                terms.iter().map(describe_positive).join(", ")
+            ),
+        }
+    }
+
+    fn describe(&self, package: &Package, versions: &VersionSet) -> String {
+        match package {
+            Package::Root | Package::Source(_) => {
+                let manifest = self.manifest_of(package);
+                format!("{} v{}", manifest.package.name, manifest.package.version)
+            }
+            Package::Major(name, major) => {
+                if versions.len() == 1 {
+                    format!("{} v{}", name, versions.iter().next().unwrap())
+                } else if versions == &self.universe(package) {
+                    format!("{} v{}.x", name, major)
+                } else {
+                    format!(
+                        "{} ({})",
+                        name,
+                        versions.iter().map(|v| format!("v{}", v)).join(" | ")
+                    )
+                }
+            }
+            Package::Proxy(name, req) => format!("{} {}", name, req),
+        }
+    }
+}
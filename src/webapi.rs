@@ -1,23 +1,40 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use reqwest::{
     blocking::{Client, Response},
     header::USER_AGENT,
 };
 use serde::Serialize;
 
-const GRILL_API: &str = "http://grillpm.vercel.app/api";
+use crate::config::Registry;
+
 const GITHUB_API: &str = "http://api.github.com";
 
-pub fn grill<S, B>(sub: S, body: &B) -> Result<Response>
+pub fn grill<S, B>(registry_name: &str, registry: &Registry, sub: S, body: &B) -> Result<Response>
 where
     S: AsRef<str>,
     B: Serialize,
 {
-    Client::new()
-        .post(format!("{}{}", GRILL_API, sub.as_ref()))
+    let res = Client::new()
+        .post(format!("{}{}", registry.api(registry_name)?, sub.as_ref()))
         .json(body)
         .send()
-        .map_err(anyhow::Error::msg)
+        .map_err(anyhow::Error::msg)?;
+
+    if let Err(err) = res.error_for_status_ref() {
+        let body = res
+            .json::<serde_json::Map<String, serde_json::Value>>()
+            .unwrap_or_default();
+        let server_err = if body.contains_key("statusCode") {
+            anyhow!("{} (status: {})", body["message"], body["statusCode"])
+        } else if let Some(message) = body.get("message") {
+            anyhow!("{}", message)
+        } else {
+            anyhow!("Request failed")
+        };
+        return Err(server_err).context(err);
+    }
+
+    Ok(res)
 }
 
 pub fn github<S, B>(sub: S, body: &B) -> Result<Response>